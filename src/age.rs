@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+/// Seconds since the last valid sample, or that divided by `max` and clamped
+/// to [0.0, 1.0]. Returns `None` until there is a sample.
+pub fn data_age(last_sample: Option<Instant>, now: Instant, max: Option<Duration>) -> Option<f32> {
+    let age = now.duration_since(last_sample?).as_secs_f32();
+    Some(match max {
+        Some(max) if !max.is_zero() => (age / max.as_secs_f32()).min(1.0),
+        _ => age,
+    })
+}
+
+/// Whether no sample has arrived for at least `window`, including before the
+/// first one.
+pub fn is_searching(last_sample: Option<Instant>, now: Instant, window: Duration) -> bool {
+    last_sample.is_none_or(|last_sample| now.duration_since(last_sample) >= window)
+}
+
+/// Whether the last sample is at least `interval` old but not yet older than
+/// `threshold`, after which the peripheral counts as disconnected.
+pub fn needs_keepalive(
+    last_sample: Option<Instant>,
+    now: Instant,
+    interval: Duration,
+    threshold: Duration,
+) -> bool {
+    last_sample.is_some_and(|last_sample| {
+        let age = now.duration_since(last_sample);
+        age >= interval && age < threshold
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_in_seconds_or_normalized() {
+        let sample = Instant::now();
+        let now = sample + Duration::from_secs(3);
+        assert_eq!(data_age(None, now, None), None);
+        assert_eq!(data_age(Some(sample), now, None), Some(3.0));
+        let max = Some(Duration::from_secs(12));
+        assert_eq!(data_age(Some(sample), now, max), Some(0.25));
+        let later = sample + Duration::from_secs(60);
+        assert_eq!(data_age(Some(sample), later, max), Some(1.0));
+        assert_eq!(data_age(Some(sample), now, Some(Duration::ZERO)), Some(3.0));
+    }
+
+    #[test]
+    fn searching_only_during_gaps_of_the_window() {
+        let window = Duration::from_secs(3);
+        let sample = Instant::now();
+        assert!(is_searching(None, sample, window));
+        assert!(!is_searching(Some(sample), sample, window));
+        let within = sample + Duration::from_millis(2999);
+        assert!(!is_searching(Some(sample), within, window));
+        assert!(is_searching(Some(sample), sample + window, window));
+        let next_sample = sample + Duration::from_secs(4);
+        assert!(!is_searching(Some(next_sample), next_sample, window));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Delays between consecutive failed attempts, doubling from half a second
+/// up to 30 seconds, optionally giving up after a number of attempts.
+pub struct Backoff {
+    max_attempts: Option<u32>,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(max_attempts: Option<u32>) -> Self {
+        Self {
+            max_attempts,
+            attempts: 0,
+        }
+    }
+
+    /// Gives up after the first attempt and `retries` more, saturating at
+    /// `u32::MAX` attempts.
+    pub fn retrying(retries: u32) -> Self {
+        Self::new(Some(retries.saturating_add(1)))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Failed attempts so far.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Records a failed attempt and returns the delay before the next one,
+    /// or `None` once there are no attempts left.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.attempts = self.attempts.saturating_add(1);
+        if self
+            .max_attempts
+            .is_some_and(|max_attempts| self.attempts >= max_attempts)
+        {
+            return None;
+        }
+        let doublings = (self.attempts - 1).min(16);
+        Some(INITIAL_DELAY.saturating_mul(1 << doublings).min(MAX_DELAY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let mut backoff = Backoff::new(None);
+        let delays: Vec<_> = (0..8).map(|_| backoff.next_delay().unwrap()).collect();
+        let expected_ms = [500, 1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000];
+        let expected: Vec<_> = expected_ms.map(Duration::from_millis).into();
+        assert_eq!(delays, expected);
+    }
+
+    #[test]
+    fn gives_up_after_the_attempts() {
+        let mut backoff = Backoff::new(Some(3));
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempts(), 3);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(INITIAL_DELAY));
+    }
+
+    #[test]
+    fn retries_follow_the_first_attempt() {
+        let mut backoff = Backoff::retrying(2);
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(Backoff::retrying(0).next_delay(), None);
+    }
+
+    #[test]
+    fn the_most_retries_dont_overflow() {
+        let mut backoff = Backoff::retrying(u32::MAX);
+        assert_eq!(backoff.max_attempts, Some(u32::MAX));
+        assert_eq!(backoff.next_delay(), Some(INITIAL_DELAY));
+        backoff.attempts = u32::MAX - 2;
+        assert_eq!(backoff.next_delay(), Some(MAX_DELAY));
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempts(), u32::MAX);
+    }
+}
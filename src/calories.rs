@@ -0,0 +1,92 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Gaps longer than this, like while reconnecting, aren't counted.
+const MAX_GAP: Duration = Duration::from_secs(10);
+const KILOJOULES_PER_KILOCALORIE: f32 = 4.184;
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+/// Approximate kilocalories burned per minute at a heart rate, using the
+/// Keytel et al. (2005) equations. Not suitable for medical use.
+pub fn kilocalories_per_minute(beats_per_minute: u8, age: u8, weight_kg: f32, sex: Sex) -> f32 {
+    let heart_rate = f32::from(beats_per_minute);
+    let age = f32::from(age);
+    let kilojoules = match sex {
+        Sex::Male => -55.0969 + 0.6309 * heart_rate + 0.1988 * weight_kg + 0.2017 * age,
+        Sex::Female => -20.4022 + 0.4472 * heart_rate - 0.1263 * weight_kg + 0.074 * age,
+    };
+    (kilojoules / KILOJOULES_PER_KILOCALORIE).max(0.0)
+}
+
+/// Accumulates the approximate kilocalories burned over a session.
+pub struct CalorieCounter {
+    age: u8,
+    weight_kg: f32,
+    sex: Sex,
+    total: f32,
+    last_sample: Option<(Instant, u8)>,
+}
+
+impl CalorieCounter {
+    pub fn new(age: u8, weight_kg: f32, sex: Sex) -> Self {
+        Self {
+            age,
+            weight_kg,
+            sex,
+            total: 0.0,
+            last_sample: None,
+        }
+    }
+
+    /// Returns the total kilocalories so far.
+    pub fn update(&mut self, beats_per_minute: u8, now: Instant) -> f32 {
+        if let Some((time, previous)) = self.last_sample {
+            let elapsed = now.duration_since(time);
+            if elapsed <= MAX_GAP {
+                let rate = kilocalories_per_minute(previous, self.age, self.weight_kg, self.sex);
+                self.total += rate * elapsed.as_secs_f32() / 60.0;
+            }
+        }
+        self.last_sample = Some((now, beats_per_minute));
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 0.001,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn keytel_rates() {
+        assert_close(kilocalories_per_minute(150, 30, 75.0, Sex::Male), 14.4596);
+        assert_close(kilocalories_per_minute(150, 30, 60.0, Sex::Female), 9.8756);
+        assert_eq!(kilocalories_per_minute(40, 20, 50.0, Sex::Male), 0.0);
+    }
+
+    #[test]
+    fn accumulates_over_the_session_skipping_gaps() {
+        let start = Instant::now();
+        let mut counter = CalorieCounter::new(30, 75.0, Sex::Male);
+        assert_eq!(counter.update(150, start), 0.0);
+        let total = counter.update(150, start + Duration::from_secs(6));
+        assert_close(total, 14.4596 / 10.0);
+        let after_gap = counter.update(150, start + Duration::from_secs(60));
+        assert_eq!(after_gap, total);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Context, Result};
+use btleplug::api::ValueNotification;
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, TimeZone};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Writes raw notifications as `<timestamp> <uuid> <hex>` lines.
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, notification: &ValueNotification) -> Result<()> {
+        let line = format_capture_line(Local::now(), notification.uuid, &notification.value);
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn format_capture_line<Tz: TimeZone>(
+    timestamp: DateTime<Tz>,
+    uuid: Uuid,
+    value: &[u8],
+) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{} {} {}",
+        timestamp.to_rfc3339_opts(SecondsFormat::Millis, false),
+        uuid,
+        hex
+    )
+}
+
+/// Parses a line written by [`CaptureWriter`].
+pub fn parse_capture_line(line: &str) -> Result<(DateTime<FixedOffset>, ValueNotification)> {
+    let mut fields = line.splitn(3, ' ');
+    let timestamp = fields.next().unwrap_or_default();
+    let uuid = fields.next().ok_or_else(|| anyhow!("Missing UUID"))?;
+    let hex = fields.next().unwrap_or_default().trim();
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(anyhow!("Malformed hex {:?}", hex));
+    }
+    let value = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((
+        DateTime::parse_from_rfc3339(timestamp)?,
+        ValueNotification {
+            uuid: Uuid::parse_str(uuid)?,
+            value,
+        },
+    ))
+}
+
+/// Reads every notification of a capture, skipping blank lines.
+pub fn read_capture(
+    path: impl AsRef<Path>,
+) -> Result<Vec<(DateTime<FixedOffset>, ValueNotification)>> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+    let mut notifications = vec![];
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let notification = parse_capture_line(&line)
+            .with_context(|| format!("{}:{}: malformed capture line", path.display(), index + 1))?;
+        notifications.push(notification);
+    }
+    Ok(notifications)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::HEART_RATE_CHARACTERISTIC_UUID;
+    use std::{env, fs, process};
+
+    #[test]
+    fn capture_lines_round_trip() {
+        let timestamp = DateTime::parse_from_rfc3339("2026-01-01T12:00:00.250+02:00").unwrap();
+        let value = [0x10, 0x48, 0x00, 0x04];
+        let line = format_capture_line(timestamp, HEART_RATE_CHARACTERISTIC_UUID, &value);
+        assert_eq!(
+            line,
+            "2026-01-01T12:00:00.250+02:00 00002a37-0000-1000-8000-00805f9b34fb 10480004"
+        );
+        let (parsed_timestamp, notification) = parse_capture_line(&line).unwrap();
+        assert_eq!(parsed_timestamp, timestamp);
+        assert_eq!(notification.uuid, HEART_RATE_CHARACTERISTIC_UUID);
+        assert_eq!(notification.value, value);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let uuid = HEART_RATE_CHARACTERISTIC_UUID;
+        assert!(parse_capture_line("2026-01-01T12:00:00.250+02:00").is_err());
+        assert!(parse_capture_line(&format!("2026-01-01T12:00:00Z {} 104", uuid)).is_err());
+        assert!(parse_capture_line(&format!("2026-01-01T12:00:00Z {} 10zz", uuid)).is_err());
+        assert!(parse_capture_line(&format!("yesterday {} 1048", uuid)).is_err());
+    }
+
+    #[test]
+    fn reads_captures_skipping_blank_lines() {
+        let path = env::temp_dir().join(format!("vrc_sa_node-capture-{}.txt", process::id()));
+        let mut writer = CaptureWriter::create(&path).unwrap();
+        for value in [vec![0x00, 72], vec![0x00, 73]] {
+            let uuid = HEART_RATE_CHARACTERISTIC_UUID;
+            writer.write(&ValueNotification { uuid, value }).unwrap();
+        }
+        let mut contents = fs::read_to_string(&path).unwrap();
+        contents.push_str("\n\n");
+        fs::write(&path, contents).unwrap();
+
+        let notifications = read_capture(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let values: Vec<_> = notifications
+            .into_iter()
+            .map(|(_, notification)| notification.value)
+            .collect();
+        assert_eq!(values, [[0x00, 72], [0x00, 73]]);
+    }
+}
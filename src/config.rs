@@ -0,0 +1,91 @@
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Arguments that make no sense in a configuration file.
+const COMMAND_LINE_ONLY: [&str; 5] = ["config", "dump_config", "list_devices", "help", "version"];
+
+/// The configuration directory of the platform.
+pub fn directory() -> Option<PathBuf> {
+    let config = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if let Some(config) = env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(config)
+    } else {
+        PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(config.join("vrc_sa_node"))
+}
+
+/// `config.toml` in the configuration directory, if there is one.
+pub fn default_path() -> Option<PathBuf> {
+    Some(directory()?.join("config.toml")).filter(|path| path.is_file())
+}
+
+/// Turns a configuration file into command line arguments, leaving out the
+/// ones given on the command line so those take precedence. Its keys are the
+/// names of the flags with underscores, as --dump-config writes them.
+pub fn file_arguments(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &Path,
+) -> Result<Vec<OsString>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the configuration {}", path.display()))?;
+    let table: Table = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse the configuration {}", path.display()))?;
+
+    let mut arguments = vec![];
+    for (key, value) in table {
+        let argument = command
+            .get_arguments()
+            .filter(|argument| !COMMAND_LINE_ONLY.contains(&argument.get_id().as_str()))
+            .find(|argument| argument.get_id() == key.as_str());
+        let (Some(argument), Some(long)) = (argument, argument.and_then(|a| a.get_long())) else {
+            bail!("{} has an unknown key {:?}", path.display(), key);
+        };
+        if matches.value_source(&key) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let flag = format!("--{}", long);
+        match (argument.get_action(), value) {
+            (ArgAction::SetTrue, Value::Boolean(set))
+            | (ArgAction::SetFalse, Value::Boolean(set)) => {
+                if set == matches!(argument.get_action(), ArgAction::SetTrue) {
+                    arguments.push(OsString::from(flag));
+                }
+            }
+            (ArgAction::Count, Value::Integer(count)) => {
+                for _ in 0..count {
+                    arguments.push(OsString::from(&flag));
+                }
+            }
+            (ArgAction::Set | ArgAction::Append, Value::Array(values)) => {
+                for value in values {
+                    arguments.push(OsString::from(format!("{}={}", flag, scalar(&key, value)?)));
+                }
+            }
+            (ArgAction::Set | ArgAction::Append, value) => {
+                arguments.push(OsString::from(format!("{}={}", flag, scalar(&key, value)?)));
+            }
+            (_, value) => bail!("{} in {} can't be {}", key, path.display(), value),
+        }
+    }
+    Ok(arguments)
+}
+
+fn scalar(key: &str, value: Value) -> Result<String> {
+    Ok(match value {
+        Value::String(value) => value,
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        value => bail!("{} can't be {}", key, value),
+    })
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Suppresses readings taken without sensor contact.
+pub struct ContactFilter {
+    suppressed: u64,
+    reported: u64,
+    reported_at: Instant,
+    warned_unsupported: bool,
+}
+
+impl ContactFilter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            suppressed: 0,
+            reported: 0,
+            reported_at: now,
+            warned_unsupported: false,
+        }
+    }
+
+    /// Returns whether a reading with the given contact status should be used.
+    pub fn accept(&mut self, contact: Option<bool>, now: Instant) -> bool {
+        let accepted = match contact {
+            Some(contact) => contact,
+            None => {
+                if !self.warned_unsupported {
+                    warn!("Peripheral doesn't report sensor contact, using all readings");
+                    self.warned_unsupported = true;
+                }
+                true
+            }
+        };
+        if !accepted {
+            self.suppressed += 1;
+        }
+
+        if self.suppressed > self.reported
+            && now.duration_since(self.reported_at) >= REPORT_INTERVAL
+        {
+            info!(
+                "Suppressed {} readings without sensor contact so far",
+                self.suppressed
+            );
+            self.reported = self.suppressed;
+            self.reported_at = now;
+        }
+        accepted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_readings_without_contact() {
+        let now = Instant::now();
+        let mut filter = ContactFilter::new(now);
+        assert!(filter.accept(Some(true), now));
+        assert!(!filter.accept(Some(false), now));
+        assert!(!filter.accept(Some(false), now));
+        assert_eq!(filter.suppressed, 2);
+        assert_eq!(filter.reported, 0);
+        assert!(!filter.accept(Some(false), now + REPORT_INTERVAL));
+        assert_eq!(filter.reported, 3);
+    }
+
+    #[test]
+    fn keeps_readings_of_straps_without_contact_reporting() {
+        let now = Instant::now();
+        let mut filter = ContactFilter::new(now);
+        assert!(filter.accept(None, now));
+        assert!(filter.accept(None, now));
+        assert!(filter.warned_unsupported);
+        assert_eq!(filter.suppressed, 0);
+    }
+}
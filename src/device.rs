@@ -0,0 +1,661 @@
+use crate::backoff::Backoff;
+use crate::passive;
+use crate::peripheral::{
+    connect_to_peripheral, CharacteristicCache, ConnectOptions, ConnectedPeripheral,
+    PeripheralTarget, HEART_RATE_CHARACTERISTIC_UUID,
+};
+use crate::simulate::{self, SimulatedHeartRate};
+use anyhow::{bail, Result};
+use btleplug::api::{BDAddr, Peripheral as _, ValueNotification, WriteType};
+use btleplug::platform::{Adapter, Peripheral};
+use chrono::{DateTime, FixedOffset, Local};
+use futures::{Future, StreamExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::time;
+use tracing::{debug, info, warn};
+use uuid::{uuid, Uuid};
+
+const HEART_RATE_CONTROL_POINT_UUID: Uuid = uuid!("00002a39-0000-1000-8000-00805f9b34fb");
+const RESET_ENERGY_EXPENDED: u8 = 0x01;
+const SIMULATION_INTERVAL: Duration = Duration::from_secs(1);
+/// How long shutting down waits for the peripheral to be released.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const MODEL_NUMBER_UUID: Uuid = uuid!("00002a24-0000-1000-8000-00805f9b34fb");
+const FIRMWARE_REVISION_UUID: Uuid = uuid!("00002a26-0000-1000-8000-00805f9b34fb");
+
+pub enum DeviceEvent {
+    Connected {
+        device: usize,
+        address: BDAddr,
+        rssi: Option<i16>,
+    },
+    /// Stopped streaming, the device is reconnecting.
+    Disconnected {
+        device: usize,
+    },
+    Battery {
+        device: usize,
+        battery_level: u8,
+    },
+    /// From the Device Information service, where the peripheral has it.
+    Information {
+        device: usize,
+        model: Option<String>,
+        firmware: Option<String>,
+    },
+    Notification {
+        device: usize,
+        notification: ValueNotification,
+        /// When the notification was originally received, if it's reprocessed.
+        captured_at: Option<DateTime<Local>>,
+    },
+    /// The peripheral didn't reset its energy expended when asked to.
+    EnergyResetFailed {
+        device: usize,
+    },
+    /// The device failed as many reconnection attempts in a row as
+    /// --backup-after allows, it keeps reconnecting.
+    ReconnectLimitReached {
+        device: usize,
+    },
+    /// The device gave up and its task stopped.
+    Failed {
+        device: usize,
+        error: anyhow::Error,
+    },
+    /// Every notification of a reprocessed capture has been sent.
+    Finished {
+        device: usize,
+    },
+}
+
+#[derive(Clone)]
+pub struct DeviceOptions {
+    pub connect: ConnectOptions,
+    pub threshold: Duration,
+    pub min_notify_interval: Duration,
+    pub passive: bool,
+    pub health_poll_interval: Option<Duration>,
+    pub battery_interval: Option<Duration>,
+    /// Failed reconnection attempts after which to report
+    /// [`DeviceEvent::ReconnectLimitReached`] and keep reconnecting, instead
+    /// of giving up after --max-reconnect-attempts.
+    pub failover_after: Option<u32>,
+}
+
+/// Drops notifications that arrive faster than a minimum interval.
+pub struct NotificationDebounce {
+    interval: Duration,
+    last_accepted: Option<Instant>,
+}
+
+impl NotificationDebounce {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_accepted: None,
+        }
+    }
+
+    pub fn accept(&mut self, now: Instant) -> bool {
+        if let Some(last_accepted) = self.last_accepted {
+            if now.duration_since(last_accepted) < self.interval {
+                return false;
+            }
+        }
+        self.last_accepted = Some(now);
+        true
+    }
+}
+
+enum Command {
+    SwitchTo(BDAddr),
+    ResetEnergyExpended,
+    Disconnect(oneshot::Sender<()>),
+}
+
+/// A peripheral that is connected, and reconnected on timeouts, by its own task.
+pub struct Device {
+    commands: mpsc::Sender<Command>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Device {
+    pub fn spawn(
+        device: usize,
+        adapter: Adapter,
+        target: PeripheralTarget,
+        options: DeviceOptions,
+        events: mpsc::Sender<DeviceEvent>,
+    ) -> Self {
+        let (commands, command_receiver) = mpsc::channel(1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let task_dropped = dropped.clone();
+        tokio::spawn(async move {
+            let result = if options.passive {
+                passive::run(device, &adapter, &target, options.threshold, &events).await
+            } else {
+                run(
+                    device,
+                    &adapter,
+                    target,
+                    &options,
+                    &events,
+                    command_receiver,
+                    &task_dropped,
+                )
+                .await
+            };
+            if let Err(error) = result {
+                let _ = events.send(DeviceEvent::Failed { device, error }).await;
+            }
+        });
+        Self { commands, dropped }
+    }
+
+    /// Sends captured notifications as if they came from a peripheral, at
+    /// their original pace unless `fast`.
+    pub fn reprocess(
+        device: usize,
+        notifications: Vec<(DateTime<FixedOffset>, ValueNotification)>,
+        fast: bool,
+        events: mpsc::Sender<DeviceEvent>,
+    ) -> Self {
+        // Reprocessing has no peripheral to send commands to.
+        let (commands, _) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut previous: Option<DateTime<FixedOffset>> = None;
+            for (captured_at, notification) in notifications {
+                if let (false, Some(previous)) = (fast, previous) {
+                    let delay = captured_at
+                        .signed_duration_since(previous)
+                        .to_std()
+                        .unwrap_or_default();
+                    time::sleep(delay).await;
+                }
+                previous = Some(captured_at);
+                let event = DeviceEvent::Notification {
+                    device,
+                    notification,
+                    captured_at: Some(captured_at.with_timezone(&Local)),
+                };
+                if events.send(event).await.is_err() {
+                    return;
+                }
+            }
+            let _ = events.send(DeviceEvent::Finished { device }).await;
+        });
+        Self {
+            commands,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Sends a simulated heart rate every second, as if it came from a
+    /// connected peripheral.
+    pub fn simulate(device: usize, fixed: Option<u8>, events: mpsc::Sender<DeviceEvent>) -> Self {
+        // The simulation has no peripheral to send commands to either.
+        let (commands, _) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let connected = DeviceEvent::Connected {
+                device,
+                address: BDAddr::default(),
+                rssi: None,
+            };
+            if events.send(connected).await.is_err() {
+                return;
+            }
+            let mut heart_rate = SimulatedHeartRate::new(fixed);
+            let started_at = Instant::now();
+            let mut interval = time::interval(SIMULATION_INTERVAL);
+            loop {
+                interval.tick().await;
+                let notification = ValueNotification {
+                    uuid: HEART_RATE_CHARACTERISTIC_UUID,
+                    value: simulate::measurement(heart_rate.next(started_at.elapsed())),
+                };
+                let event = DeviceEvent::Notification {
+                    device,
+                    notification,
+                    captured_at: None,
+                };
+                if events.send(event).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            commands,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Number of notifications dropped by the debounce so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Disconnects from the current peripheral and connects to the given one instead.
+    pub async fn switch_to(&self, address: BDAddr) {
+        let _ = self.commands.send(Command::SwitchTo(address)).await;
+    }
+
+    /// Resets the energy expended counter of the connected peripheral.
+    pub async fn reset_energy_expended(&self) {
+        let _ = self.commands.send(Command::ResetEnergyExpended).await;
+    }
+
+    /// Unsubscribes from and disconnects the connected peripheral and stops
+    /// its task, so the next launch doesn't find a stale connection.
+    pub async fn disconnect(&self) {
+        let (done, disconnected) = oneshot::channel();
+        let disconnect = async {
+            if self.commands.send(Command::Disconnect(done)).await.is_ok() {
+                let _ = disconnected.await;
+            }
+        };
+        if time::timeout(DISCONNECT_TIMEOUT, disconnect).await.is_err() {
+            info!("Timed out while disconnecting");
+        }
+    }
+}
+
+/// Whether a health poll shows the peripheral dropped, a failed poll counting
+/// as dropped too so it's reconnected rather than ending the stream.
+fn reports_disconnected(is_connected: btleplug::Result<bool>) -> bool {
+    !matches!(is_connected, Ok(true))
+}
+
+async fn reset_energy_expended(peripheral: &Peripheral, name: &str) -> Result<()> {
+    let control_point = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == HEART_RATE_CONTROL_POINT_UUID);
+    let Some(control_point) = control_point else {
+        bail!("{} has no heart rate control point", name);
+    };
+    peripheral
+        .write(
+            &control_point,
+            &[RESET_ENERGY_EXPENDED],
+            WriteType::WithResponse,
+        )
+        .await?;
+    info!("Reset energy expended of {}", name);
+    Ok(())
+}
+
+/// Reads the battery level without holding up the heart rate stream, once
+/// or every `interval`. Skipped for peripherals without a battery level.
+fn spawn_battery_read(
+    tasks: &mut JoinSet<()>,
+    device: usize,
+    connected_peripheral: &ConnectedPeripheral,
+    interval: Option<Duration>,
+    events: mpsc::Sender<DeviceEvent>,
+) {
+    let Some(characteristic) = connected_peripheral.characteristics.battery_level.clone() else {
+        return;
+    };
+    let peripheral = connected_peripheral.peripheral.clone();
+    let name = connected_peripheral.name.clone();
+    tasks.spawn(read_battery_levels(
+        device,
+        name,
+        interval,
+        move || {
+            let peripheral = peripheral.clone();
+            let characteristic = characteristic.clone();
+            async move { peripheral.read(&characteristic).await }
+        },
+        events,
+    ));
+}
+
+async fn read_battery_levels<F, Fut>(
+    device: usize,
+    name: String,
+    interval: Option<Duration>,
+    mut read: F,
+    events: mpsc::Sender<DeviceEvent>,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = btleplug::Result<Vec<u8>>>,
+{
+    loop {
+        match read().await {
+            Ok(value) if !value.is_empty() => {
+                info!("Battery level of {}: {}", name, value[0]);
+                let battery = DeviceEvent::Battery {
+                    device,
+                    battery_level: value[0],
+                };
+                if events.send(battery).await.is_err() {
+                    return;
+                }
+            }
+            Ok(_) => info!("Battery level of {} is empty", name),
+            Err(error) => info!("Failed to read battery level of {}: {}", name, error),
+        }
+        let Some(interval) = interval else {
+            return;
+        };
+        time::sleep(interval).await;
+    }
+}
+
+/// Reads the model number and firmware revision, if the peripheral has them.
+fn spawn_information_read(
+    device: usize,
+    connected_peripheral: &ConnectedPeripheral,
+    events: mpsc::Sender<DeviceEvent>,
+) {
+    let peripheral = connected_peripheral.peripheral.clone();
+    let name = connected_peripheral.name.clone();
+    tokio::spawn(async move {
+        let characteristics = peripheral.characteristics();
+        let read = |uuid: Uuid| {
+            let characteristic = characteristics
+                .iter()
+                .find(|characteristic| characteristic.uuid == uuid)
+                .cloned();
+            let peripheral = peripheral.clone();
+            async move {
+                let value = peripheral.read(&characteristic?).await.ok()?;
+                Some(
+                    String::from_utf8_lossy(&value)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                )
+            }
+        };
+        let model = read(MODEL_NUMBER_UUID).await;
+        let firmware = read(FIRMWARE_REVISION_UUID).await;
+        if model.is_none() && firmware.is_none() {
+            return;
+        }
+        info!("{} is model {:?} with firmware {:?}", name, model, firmware);
+        let information = DeviceEvent::Information {
+            device,
+            model,
+            firmware,
+        };
+        let _ = events.send(information).await;
+    });
+}
+
+async fn run(
+    device: usize,
+    adapter: &Adapter,
+    mut target: PeripheralTarget,
+    options: &DeviceOptions,
+    events: &mpsc::Sender<DeviceEvent>,
+    mut commands: mpsc::Receiver<Command>,
+    dropped: &AtomicU64,
+) -> Result<()> {
+    let mut cache = None;
+    // Reset by heart rate notifications, so it only counts connections that
+    // never streamed.
+    let max_attempts = match options.failover_after {
+        Some(_) => None,
+        None => options.connect.max_reconnect_attempts,
+    };
+    let mut backoff = Backoff::new(max_attempts);
+    let mut reconnecting = false;
+    loop {
+        if reconnecting {
+            let Some(delay) = backoff.next_delay() else {
+                bail!(
+                    "Gave up reconnecting after {} attempts without data",
+                    backoff.attempts()
+                );
+            };
+            if options.failover_after == Some(backoff.attempts()) {
+                let limit_reached = DeviceEvent::ReconnectLimitReached { device };
+                if events.send(limit_reached).await.is_err() {
+                    return Ok(());
+                }
+            }
+            info!("Reconnecting in {:?}", delay);
+            time::sleep(delay).await;
+        }
+        let first_connection = !reconnecting;
+        reconnecting = true;
+        let connect = connect_to_peripheral(adapter, &target, &options.connect, cache.as_ref());
+        let mut connected_peripheral = match connect.await {
+            Ok(connected_peripheral) => connected_peripheral,
+            Err(error) if first_connection && options.failover_after.is_none() => {
+                return Err(error)
+            }
+            // Counted as a failed attempt toward the reconnect limit.
+            Err(error) => {
+                info!("Failed to reconnect: {}", error);
+                continue;
+            }
+        };
+        cache = Some(CharacteristicCache {
+            address: connected_peripheral.address,
+            characteristics: connected_peripheral.characteristics.clone(),
+        });
+        target = PeripheralTarget::Address(connected_peripheral.address);
+        let connected = DeviceEvent::Connected {
+            device,
+            address: connected_peripheral.address,
+            rssi: connected_peripheral.rssi,
+        };
+        if events.send(connected).await.is_err() {
+            return Ok(());
+        }
+        // Dropped with the connection, which stops the periodic battery reads.
+        let mut tasks = JoinSet::new();
+        spawn_battery_read(
+            &mut tasks,
+            device,
+            &connected_peripheral,
+            options.battery_interval,
+            events.clone(),
+        );
+        spawn_information_read(device, &connected_peripheral, events.clone());
+
+        let threshold = options.threshold;
+        let mut debounce = NotificationDebounce::new(options.min_notify_interval);
+        let mut deadline = time::Instant::now() + threshold;
+        // The poll only runs while connected, so it can't race with a reconnect.
+        let poll_interval = options.health_poll_interval.unwrap_or(threshold);
+        let mut health_poll =
+            time::interval_at(time::Instant::now() + poll_interval, poll_interval);
+        loop {
+            tokio::select! {
+                Some(mut notification) = connected_peripheral.notification_stream.next() => {
+                    // Only heart rate notifications show the peripheral is streaming.
+                    let heart_rate = connected_peripheral.characteristics.heart_rate.uuid;
+                    let is_heart_rate = notification.uuid == heart_rate;
+                    if is_heart_rate && heart_rate != HEART_RATE_CHARACTERISTIC_UUID {
+                        debug!(
+                            "Raw data of {} on {}: {:02x?}",
+                            connected_peripheral.name,
+                            heart_rate,
+                            notification.value
+                        );
+                        // Parsed as a standard measurement from here on.
+                        notification.uuid = HEART_RATE_CHARACTERISTIC_UUID;
+                    }
+                    if is_heart_rate {
+                        deadline = time::Instant::now() + threshold;
+                        backoff.reset();
+                    }
+                    if is_heart_rate && !debounce.accept(Instant::now()) {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    info!(
+                        "Received data from {} [{:?}]: {:?}",
+                        connected_peripheral.name, notification.uuid, notification.value
+                    );
+                    let event = DeviceEvent::Notification {
+                        device,
+                        notification,
+                        captured_at: None,
+                    };
+                    if events.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                _ = time::sleep_until(deadline) => {
+                    info!(
+                        "Timed out while waiting for a notification from {}",
+                        connected_peripheral.name
+                    );
+                    break;
+                }
+                _ = health_poll.tick(), if options.health_poll_interval.is_some() => {
+                    let is_connected = connected_peripheral.peripheral.is_connected().await;
+                    if let Err(error) = &is_connected {
+                        info!(
+                            "Failed to check whether {} is connected: {}",
+                            connected_peripheral.name, error
+                        );
+                    }
+                    if reports_disconnected(is_connected) {
+                        info!(
+                            "{} reports being disconnected, reconnecting",
+                            connected_peripheral.name
+                        );
+                        break;
+                    }
+                }
+                Some(command) = commands.recv() => match command {
+                    Command::SwitchTo(address) => {
+                        info!(
+                            "Switching from {} to {}",
+                            connected_peripheral.address, address
+                        );
+                        if let Err(error) = connected_peripheral.peripheral.disconnect().await {
+                            info!(
+                                "Failed to disconnect from {}: {}",
+                                connected_peripheral.name, error
+                            );
+                        }
+                        target = PeripheralTarget::Address(address);
+                        reconnecting = false;
+                        break;
+                    }
+                    Command::ResetEnergyExpended => {
+                        let peripheral = &connected_peripheral.peripheral;
+                        let name = &connected_peripheral.name;
+                        if let Err(error) = reset_energy_expended(peripheral, name).await {
+                            warn!("Failed to reset the energy expended of {}: {}", name, error);
+                            let failed = DeviceEvent::EnergyResetFailed { device };
+                            if events.send(failed).await.is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Command::Disconnect(done) => {
+                        let peripheral = &connected_peripheral.peripheral;
+                        let heart_rate = &connected_peripheral.characteristics.heart_rate;
+                        if let Err(error) = peripheral.unsubscribe(heart_rate).await {
+                            info!(
+                                "Failed to unsubscribe from {}: {}",
+                                connected_peripheral.name, error
+                            );
+                        }
+                        match peripheral.disconnect().await {
+                            Ok(()) => info!("Disconnected from {}", connected_peripheral.name),
+                            Err(error) => info!(
+                                "Failed to disconnect from {}: {}",
+                                connected_peripheral.name, error
+                            ),
+                        }
+                        let _ = done.send(());
+                        return Ok(());
+                    }
+                },
+            }
+        }
+        // Sent before reconnecting, as that can take a while.
+        if events
+            .send(DeviceEvent::Disconnected { device })
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn health_poll_reconnects_unless_connected() {
+        assert!(!reports_disconnected(Ok(true)));
+        assert!(reports_disconnected(Ok(false)));
+        assert!(reports_disconnected(Err(btleplug::Error::NotConnected)));
+    }
+
+    #[test]
+    fn debounce_drops_notifications_within_the_interval() {
+        let start = Instant::now();
+        let mut debounce = NotificationDebounce::new(Duration::from_millis(100));
+        assert!(debounce.accept(start));
+        assert!(!debounce.accept(start + Duration::from_millis(5)));
+        assert!(!debounce.accept(start + Duration::from_millis(99)));
+        assert!(debounce.accept(start + Duration::from_millis(100)));
+        // Measured from the last accepted notification, not the last dropped.
+        assert!(!debounce.accept(start + Duration::from_millis(150)));
+        assert!(debounce.accept(start + Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn zero_interval_keeps_every_notification() {
+        let now = Instant::now();
+        let mut debounce = NotificationDebounce::new(Duration::ZERO);
+        assert!((0..3).all(|_| debounce.accept(now)));
+    }
+
+    #[tokio::test]
+    async fn battery_read_does_not_hold_up_the_stream() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (events, mut received) = mpsc::channel(1);
+        let (release, read) = oneshot::channel::<()>();
+        let mut read = Some(read);
+        let read_calls = calls.clone();
+        let mut tasks = JoinSet::new();
+        tasks.spawn(read_battery_levels(
+            0,
+            String::from("Strap"),
+            None,
+            move || {
+                let read = read.take();
+                let calls = read_calls.clone();
+                async move {
+                    if let Some(read) = read {
+                        let _ = read.await;
+                    }
+                    calls.lock().unwrap().push("battery read");
+                    Ok(vec![80])
+                }
+            },
+            events,
+        ));
+        calls.lock().unwrap().push("notification");
+        release.send(()).unwrap();
+        let battery = received.recv().await;
+        assert!(matches!(
+            battery,
+            Some(DeviceEvent::Battery {
+                device: 0,
+                battery_level: 80
+            })
+        ));
+        assert_eq!(*calls.lock().unwrap(), ["notification", "battery read"]);
+        assert!(received.recv().await.is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(30);
+const MIN_SAMPLES: usize = 5;
+const RESERVE_WEIGHT: f32 = 0.8;
+/// Rise in bpm per second that counts as the steepest possible climb.
+const MAX_RISE: f32 = 1.0;
+
+/// An approximate, non-medical effort index in [0.0, 1.0].
+///
+/// The index is `0.8 * reserve + 0.2 * rise`, where `reserve` is the fraction
+/// of the heart rate reserve in use, `(bpm - resting) / (max - resting)`, and
+/// `rise` is how fast the heart rate climbed over the window relative to
+/// `MAX_RISE`. Both terms are clamped to [0.0, 1.0]. Returns `None` until at
+/// least `MIN_SAMPLES` samples spanning some time are available.
+pub fn effort_index(samples: &[(Instant, u8)], resting_hr: u8, max_hr: u8) -> Option<f32> {
+    if samples.len() < MIN_SAMPLES || max_hr <= resting_hr {
+        return None;
+    }
+    let (first_time, first_bpm) = samples[0];
+    let (last_time, last_bpm) = samples[samples.len() - 1];
+    let elapsed = last_time.duration_since(first_time).as_secs_f32();
+    if elapsed <= 0.0 {
+        return None;
+    }
+
+    let reserve =
+        (f32::from(last_bpm) - f32::from(resting_hr)) / (f32::from(max_hr) - f32::from(resting_hr));
+    let rise = (f32::from(last_bpm) - f32::from(first_bpm)) / elapsed / MAX_RISE;
+    let index =
+        RESERVE_WEIGHT * reserve.clamp(0.0, 1.0) + (1.0 - RESERVE_WEIGHT) * rise.clamp(0.0, 1.0);
+    Some(index.clamp(0.0, 1.0))
+}
+
+/// Keeps the recent samples the effort index is computed from.
+pub struct EffortTracker {
+    resting_hr: u8,
+    max_hr: u8,
+    capacity: usize,
+    samples: VecDeque<(Instant, u8)>,
+}
+
+impl EffortTracker {
+    /// Keeps at most `capacity` samples, however fast they arrive.
+    pub fn new(resting_hr: u8, max_hr: u8, capacity: usize) -> Self {
+        Self {
+            resting_hr,
+            max_hr,
+            capacity: capacity.max(MIN_SAMPLES),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn update(&mut self, beats_per_minute: u8, now: Instant) -> Option<f32> {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, beats_per_minute));
+        while let Some((time, _)) = self.samples.front() {
+            if now.duration_since(*time) <= WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        effort_index(self.samples.make_contiguous(), self.resting_hr, self.max_hr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_bounded_by_retention() {
+        let start = Instant::now();
+        let mut tracker = EffortTracker::new(60, 190, 50);
+        for index in 0..10_000 {
+            tracker.update(120, start + Duration::from_millis(index));
+        }
+        assert_eq!(tracker.samples.len(), 50);
+    }
+
+    fn samples(heart_rates: &[u8]) -> Vec<(Instant, u8)> {
+        let start = Instant::now();
+        let seconds = (0..).map(|second| start + Duration::from_secs(second));
+        seconds.zip(heart_rates.iter().copied()).collect()
+    }
+
+    fn assert_close(index: Option<f32>, expected: f32) {
+        let index = index.unwrap();
+        assert!((index - expected).abs() < 1e-6, "{} != {}", index, expected);
+    }
+
+    #[test]
+    fn index_combines_reserve_and_rise() {
+        let steady = samples(&[120, 120, 120, 120, 120]);
+        assert_close(effort_index(&steady, 60, 180), 0.4);
+        let climbing = samples(&[100, 100, 100, 100, 120]);
+        assert_close(effort_index(&climbing, 60, 180), 0.6);
+        let falling = samples(&[160, 150, 140, 130, 120]);
+        assert_close(effort_index(&falling, 60, 180), 0.4);
+        let beyond = samples(&[150, 170, 190, 210, 230]);
+        assert_close(effort_index(&beyond, 60, 180), 1.0);
+    }
+
+    #[test]
+    fn nothing_until_enough_samples() {
+        assert_eq!(effort_index(&samples(&[120; 4]), 60, 180), None);
+        let at_once = vec![(Instant::now(), 120); 5];
+        assert_eq!(effort_index(&at_once, 60, 180), None);
+        assert_eq!(effort_index(&samples(&[120; 5]), 180, 60), None);
+    }
+}
@@ -0,0 +1,110 @@
+/// Raw values at or above this are close enough to wrapping to reset the counter.
+pub const NEAR_OVERFLOW: u16 = 0xF000;
+
+/// Accumulates the 16-bit energy expended counter across wraparounds and resets.
+pub struct EnergyAccumulator {
+    last: Option<u16>,
+    total: u64,
+    reset_requested: bool,
+    /// Set once the peripheral failed to reset, so it isn't asked again.
+    reset_failed: bool,
+}
+
+impl EnergyAccumulator {
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            total: 0,
+            reset_requested: false,
+            reset_failed: false,
+        }
+    }
+
+    /// Takes the next value as a new starting point, like after reconnecting.
+    pub fn restart(&mut self) {
+        self.last = None;
+        self.reset_requested = false;
+    }
+
+    /// Makes the next decrease count as a reset to zero instead of a wraparound.
+    pub fn expect_reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    /// Whether to ask the peripheral for a reset, which is done once and
+    /// not again after it failed.
+    pub fn can_request_reset(&self) -> bool {
+        !self.reset_requested && !self.reset_failed
+    }
+
+    /// Counts the next decrease as a wraparound again, as the reset didn't
+    /// happen.
+    pub fn reset_failed(&mut self) {
+        self.reset_requested = false;
+        self.reset_failed = true;
+    }
+
+    /// Returns the kilojoules expended since the first value.
+    pub fn update(&mut self, energy_expended: u16) -> u64 {
+        if let Some(last) = self.last {
+            let delta = if energy_expended >= last {
+                u64::from(energy_expended - last)
+            } else if self.reset_requested {
+                self.reset_requested = false;
+                u64::from(energy_expended)
+            } else {
+                u64::from(energy_expended) + (1 << 16) - u64::from(last)
+            };
+            self.total += delta;
+        }
+        self.last = Some(energy_expended);
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraparound_adds_the_16_bit_range() {
+        let mut energy = EnergyAccumulator::new();
+        assert_eq!(energy.update(65_000), 0);
+        assert_eq!(energy.update(65_500), 500);
+        assert_eq!(energy.update(200), 736);
+        assert_eq!(energy.update(1_200), 1_736);
+    }
+
+    #[test]
+    fn requested_reset_counts_from_zero() {
+        let mut energy = EnergyAccumulator::new();
+        energy.update(NEAR_OVERFLOW);
+        assert!(energy.can_request_reset());
+        energy.expect_reset();
+        assert!(!energy.can_request_reset());
+        assert_eq!(energy.update(10), 10);
+        // Later decreases are wraparounds again.
+        energy.update(65_530);
+        assert_eq!(energy.update(4), 65_540);
+    }
+
+    #[test]
+    fn failed_reset_counts_as_wraparound() {
+        let mut energy = EnergyAccumulator::new();
+        energy.update(65_000);
+        energy.expect_reset();
+        energy.reset_failed();
+        assert!(!energy.can_request_reset());
+        assert_eq!(energy.update(100), 636);
+    }
+
+    #[test]
+    fn restart_takes_a_new_starting_point() {
+        let mut energy = EnergyAccumulator::new();
+        energy.update(100);
+        energy.update(300);
+        energy.restart();
+        assert_eq!(energy.update(50), 200);
+        assert_eq!(energy.update(70), 220);
+    }
+}
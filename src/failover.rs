@@ -0,0 +1,67 @@
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Primary,
+    Backup,
+}
+
+/// Decides whether the primary or the backup peripheral drives the outputs.
+#[derive(Default)]
+pub struct Failover {
+    promoted: bool,
+}
+
+impl Failover {
+    /// The primary used up its reconnect limit, so the backup takes over
+    /// until the primary streams again.
+    pub fn primary_failed(&mut self) {
+        if !self.promoted {
+            info!("Primary peripheral failed, promoting backup");
+            self.promoted = true;
+        }
+    }
+
+    /// Returns whether a notification from the given role should be used.
+    pub fn accept(&mut self, role: Role) -> bool {
+        match role {
+            Role::Primary => {
+                if self.promoted {
+                    info!("Primary peripheral recovered, demoting backup");
+                    self.promoted = false;
+                }
+                true
+            }
+            Role::Backup => self.promoted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_is_ignored_while_primary_is_healthy() {
+        let mut failover = Failover::default();
+        assert!(failover.accept(Role::Primary));
+        assert!(!failover.accept(Role::Backup));
+    }
+
+    #[test]
+    fn backup_is_promoted_on_primary_failure() {
+        let mut failover = Failover::default();
+        failover.primary_failed();
+        assert!(failover.accept(Role::Backup));
+        assert!(failover.accept(Role::Backup));
+    }
+
+    #[test]
+    fn backup_is_demoted_on_primary_recovery() {
+        let mut failover = Failover::default();
+        failover.primary_failed();
+        assert!(failover.accept(Role::Backup));
+        assert!(failover.accept(Role::Primary));
+        assert!(!failover.accept(Role::Backup));
+    }
+}
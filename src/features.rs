@@ -0,0 +1,117 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// An OSC output that --features can select.
+#[derive(ValueEnum, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum OscFeature {
+    /// HeartRate
+    Bpm,
+    /// HeartRateCalm, needs --calm-bpm
+    Calm,
+    /// HeartRateEffortIndex, needs --resting-hr and --max-hr
+    Effort,
+    /// HeartRateSpike, needs --spike-delta
+    Spike,
+    /// HeartRateAge, needs --age-interval-ms
+    Age,
+    /// HeartRateDebug
+    Debug,
+    /// HeartRateCalories, needs --age, --weight-kg and --sex
+    Calories,
+    /// HeartRateReconnected
+    Reconnected,
+    /// HeartRateStarted
+    Started,
+    /// HeartRateConnected
+    Connected,
+    /// HeartRateBattery and HeartRateBatteryInt, needs the battery level not to be skipped
+    Battery,
+    /// HeartRateDelta, needs --baseline-delta and --resting-hr
+    Delta,
+    /// HeartRateSearching, needs --searching-window
+    Searching,
+    /// HeartRateSmoothed, needs --smoothing
+    Smoothed,
+    /// HeartRateZone and its boundaries, from --zone-max or --max-heart-rate
+    Zone,
+    /// HeartRateBeat
+    HeartBeat,
+    /// HeartRateEnergy, when the peripheral reports the energy expended
+    Energy,
+    /// HeartRateHRV, needs --hrv-window
+    Hrv,
+}
+
+impl OscFeature {
+    fn requirement(self) -> &'static str {
+        match self {
+            Self::Calm => "--calm-bpm",
+            Self::Effort => "--resting-hr and --max-hr",
+            Self::Spike => "--spike-delta",
+            Self::Age => "--age-interval-ms",
+            Self::Calories => "--age, --weight-kg and --sex",
+            Self::Delta => "--baseline-delta and --resting-hr",
+            Self::Searching => "--searching-window",
+            Self::Smoothed => "--smoothing",
+            Self::Hrv => "--hrv-window",
+            Self::Battery => "no --skip-battery",
+            Self::Bpm
+            | Self::Debug
+            | Self::Reconnected
+            | Self::Started
+            | Self::Connected
+            | Self::Zone
+            | Self::HeartBeat
+            | Self::Energy => "nothing",
+        }
+    }
+}
+
+/// Picks the outputs to send: the selected ones if there is a selection,
+/// otherwise the ones enabled by their own flags. Selected outputs have to
+/// be configurable with the given arguments.
+pub fn resolve(
+    selected: Option<&[OscFeature]>,
+    enabled: &[OscFeature],
+    configurable: &[OscFeature],
+) -> Result<Vec<OscFeature>> {
+    let Some(selected) = selected else {
+        return Ok(enabled.to_vec());
+    };
+    for feature in selected {
+        if !configurable.contains(feature) {
+            bail!(
+                "The {} feature needs {}",
+                feature
+                    .to_possible_value()
+                    .map_or_else(String::new, |value| value.get_name().to_string()),
+                feature.requirement()
+            );
+        }
+    }
+    Ok(selected.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_replaces_the_individual_flags() {
+        let enabled = [OscFeature::Bpm, OscFeature::Battery];
+        let configurable = [OscFeature::Bpm, OscFeature::Battery, OscFeature::Zone];
+        let resolved = resolve(None, &enabled, &configurable).unwrap();
+        assert_eq!(resolved, enabled);
+        let selected = [OscFeature::Zone];
+        let resolved = resolve(Some(&selected), &enabled, &configurable).unwrap();
+        assert_eq!(resolved, selected);
+    }
+
+    #[test]
+    fn selected_features_need_their_arguments() {
+        let error = resolve(Some(&[OscFeature::Calm]), &[], &[]).unwrap_err();
+        assert_eq!(error.to_string(), "The calm feature needs --calm-bpm");
+    }
+}
@@ -0,0 +1,150 @@
+use crate::sample::Sample;
+use crate::sink::LogSink;
+use anyhow::{bail, Result};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Writes samples as JSON lines to a named pipe, dropping them while no
+/// reader has it open.
+pub struct FifoSink {
+    path: PathBuf,
+    file: Option<File>,
+    dropped: u64,
+}
+
+impl FifoSink {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        match path.metadata() {
+            Ok(metadata) if metadata.file_type().is_fifo() => {}
+            Ok(_) => bail!("{} exists and isn't a named pipe", path.display()),
+            Err(_) => {
+                let c_path = CString::new(path.as_os_str().as_bytes())?;
+                if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } != 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                info!("Created named pipe {}", path.display());
+            }
+        }
+        Ok(Self {
+            path,
+            file: None,
+            dropped: 0,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            // Opening a pipe for writing without blocking fails while
+            // there is no reader.
+            match OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path)
+            {
+                Ok(file) => self.file = Some(file),
+                Err(error) if error.raw_os_error() == Some(libc::ENXIO) => {
+                    self.count_dropped();
+                    return Ok(());
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        // A single write of up to PIPE_BUF bytes is atomic, so readers
+        // never see part of a line, unless it's longer.
+        let line = format!("{}\n", line);
+        match file.write(line.as_bytes()) {
+            Ok(written) if written == line.len() => {}
+            Ok(_) => self.count_dropped(),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => self.count_dropped(),
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                self.file = None;
+                self.count_dropped();
+            }
+            Err(error) => return Err(error),
+        }
+        Ok(())
+    }
+
+    fn count_dropped(&mut self) {
+        self.dropped += 1;
+        if self.dropped.is_power_of_two() {
+            info!(
+                "Dropped {} samples for {} without a reader",
+                self.dropped,
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl LogSink for FifoSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()> {
+        self.write_line(&sample.to_json(battery_level))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::process;
+
+    fn sample() -> Sample {
+        Sample {
+            timestamp: String::from("2026-01-01T00:00:00+00:00"),
+            heart_rate: 72,
+            rr_intervals: vec![],
+            contact: None,
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        }
+    }
+
+    #[test]
+    fn drops_samples_without_a_reader() {
+        let path = env::temp_dir().join(format!("vrc_sa_node-fifo-{}", process::id()));
+        let mut sink = FifoSink::create(&path).unwrap();
+        sink.write(&sample(), None).unwrap();
+        assert_eq!(sink.dropped, 1);
+
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        sink.write(&sample(), Some(85)).unwrap();
+        assert_eq!(sink.dropped, 1);
+        // Closing the writing end lets the read end at the line.
+        drop(sink);
+        let mut line = String::new();
+        reader.read_to_string(&mut line).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(line, format!("{}\n", sample().to_json(Some(85))));
+    }
+
+    #[test]
+    fn refuses_files_other_than_pipes() {
+        let path = env::temp_dir().join(format!("vrc_sa_node-not-fifo-{}", process::id()));
+        fs::write(&path, "").unwrap();
+        let created = FifoSink::create(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(created.is_err());
+    }
+}
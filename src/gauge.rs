@@ -0,0 +1,114 @@
+use std::time::{Duration, Instant};
+
+/// Eases a value toward its latest target over a fixed duration.
+pub struct GaugeEasing {
+    duration: Duration,
+    from: f32,
+    target: Option<f32>,
+    started_at: Instant,
+    last_value: Option<f32>,
+}
+
+impl GaugeEasing {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            from: 0.0,
+            target: None,
+            started_at: Instant::now(),
+            last_value: None,
+        }
+    }
+
+    /// Forgets the current target, so the next one is taken as is.
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.last_value = None;
+    }
+
+    pub fn set_target(&mut self, target: f32, now: Instant) {
+        // The first target is taken as is, there is nothing to ease from yet.
+        self.from = match self.target {
+            Some(_) => self.value(now),
+            None => target,
+        };
+        self.target = Some(target);
+        self.started_at = now;
+    }
+
+    pub fn value(&self, now: Instant) -> f32 {
+        let Some(target) = self.target else {
+            return self.from;
+        };
+        let progress = if self.duration.is_zero() {
+            1.0
+        } else {
+            (now.duration_since(self.started_at).as_secs_f32() / self.duration.as_secs_f32())
+                .min(1.0)
+        };
+        // Smoothstep, so the gauge starts and settles gently.
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+        self.from + (target - self.from) * eased
+    }
+
+    /// Returns the current value when it changed since the last tick.
+    pub fn tick(&mut self, now: Instant) -> Option<f32> {
+        self.target?;
+        let value = self.value(now);
+        if self.last_value == Some(value) {
+            return None;
+        }
+        self.last_value = Some(value);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges_to_the_target_within_the_duration() {
+        let duration = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut gauge = GaugeEasing::new(duration);
+        gauge.set_target(0.2, start);
+        assert_eq!(gauge.tick(start), Some(0.2));
+        gauge.set_target(0.8, start);
+        let mut previous = 0.2;
+        for step in 1..=10 {
+            let value = gauge
+                .tick(start + duration * step / 10)
+                .expect("the value moves every tick");
+            assert!(previous < value && value <= 0.8);
+            previous = value;
+        }
+        assert_eq!(previous, 0.8);
+        assert_eq!(gauge.tick(start + duration * 2), None);
+    }
+
+    #[test]
+    fn eases_from_where_it_was_when_retargeted() {
+        let duration = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut gauge = GaugeEasing::new(duration);
+        gauge.set_target(0.0, start);
+        gauge.set_target(1.0, start);
+        let halfway = start + duration / 2;
+        assert_eq!(gauge.value(halfway), 0.5);
+        gauge.set_target(0.0, halfway);
+        assert_eq!(gauge.value(halfway), 0.5);
+        assert_eq!(gauge.value(halfway + duration), 0.0);
+    }
+
+    #[test]
+    fn reset_takes_the_next_target_as_is() {
+        let start = Instant::now();
+        let mut gauge = GaugeEasing::new(Duration::from_secs(1));
+        assert_eq!(gauge.tick(start), None);
+        gauge.set_target(0.3, start);
+        gauge.reset();
+        gauge.set_target(0.9, start);
+        assert_eq!(gauge.tick(start), Some(0.9));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::age;
+use crate::energy::EnergyAccumulator;
+use crate::measurement::HeartRateMeasurement;
+use crate::telemetry::{self, SessionState, TelemetryOptions};
+use anyhow::Result;
+use rosc::OscPacket;
+use std::time::{Duration, Instant};
+
+/// A peripheral streaming next to the primary one into its own numbered
+/// parameters, like HeartRate2 for the second --peripheral-address.
+pub struct GroupMember {
+    /// The position of its --peripheral-address, 1 for the first.
+    pub number: usize,
+    state: SessionState,
+    battery_level: Option<u8>,
+    connected_before: bool,
+    awaiting_first_sample: bool,
+    last_sample: Option<Instant>,
+    energy: EnergyAccumulator,
+}
+
+impl GroupMember {
+    pub fn new(number: usize, options: TelemetryOptions) -> Result<Self> {
+        Ok(Self {
+            number,
+            state: SessionState::new(options)?,
+            battery_level: None,
+            connected_before: false,
+            awaiting_first_sample: false,
+            last_sample: None,
+            energy: EnergyAccumulator::new(),
+        })
+    }
+
+    pub fn unit_range_addresses(&self) -> Vec<String> {
+        self.state.unit_range_addresses()
+    }
+
+    pub fn battery_level(&self) -> Option<u8> {
+        self.battery_level
+    }
+
+    pub fn connected(&mut self) -> Vec<OscPacket> {
+        self.battery_level = None;
+        self.energy.restart();
+        let mut packets = self.state.connected(self.connected_before);
+        packets.extend(self.state.connection(true));
+        self.connected_before = true;
+        self.awaiting_first_sample = true;
+        packets
+    }
+
+    pub fn disconnected(&mut self) -> Vec<OscPacket> {
+        self.state.disconnected();
+        self.state.connection(false)
+    }
+
+    pub fn battery(&mut self, level: u8) -> Vec<OscPacket> {
+        self.battery_level = Some(level);
+        self.state.battery(level)
+    }
+
+    /// Accumulates the energy expended of a measurement, if it reports it.
+    pub fn energy_expended(&mut self, measurement: &HeartRateMeasurement) -> Option<u64> {
+        measurement
+            .energy_expended
+            .map(|energy_expended| self.energy.update(energy_expended))
+    }
+
+    pub fn measurement(
+        &mut self,
+        measurement: &HeartRateMeasurement,
+        energy_expended: Option<u64>,
+    ) -> Vec<OscPacket> {
+        if self.awaiting_first_sample {
+            self.awaiting_first_sample = false;
+            self.state.started();
+        }
+        self.last_sample = Some(Instant::now());
+        let beats_per_minute = measurement.beats_per_minute();
+        telemetry::process_measurement(
+            measurement,
+            beats_per_minute,
+            energy_expended,
+            self.battery_level,
+            &mut self.state,
+        )
+    }
+
+    pub fn gauge_tick(&mut self, now: Instant) -> Vec<OscPacket> {
+        self.state.gauge_tick(now)
+    }
+
+    pub fn keepalive(
+        &self,
+        now: Instant,
+        interval: Duration,
+        threshold: Duration,
+    ) -> Vec<OscPacket> {
+        if !age::needs_keepalive(self.last_sample, now, interval, threshold) {
+            return vec![];
+        }
+        self.state.keepalive(self.battery_level, now)
+    }
+
+    pub fn avatar_changed(&self, now: Instant) -> Vec<OscPacket> {
+        self.state.avatar_changed(self.battery_level, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::OscFeature;
+    use crate::measurement::parse_heart_rate_measurement;
+    use rosc::OscType;
+
+    fn started(member: &mut GroupMember) -> Vec<Vec<OscType>> {
+        let measurement = parse_heart_rate_measurement(&[0x00, 72]).unwrap();
+        let packets = member.measurement(&measurement, None);
+        packets
+            .into_iter()
+            .filter_map(|packet| match packet {
+                OscPacket::Message(message) if message.addr.ends_with("Started") => {
+                    Some(message.args)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_started_pulse_per_connection() {
+        let options = telemetry::tests::options(&[OscFeature::Started]);
+        let mut member = GroupMember::new(2, options).unwrap();
+        assert!(started(&mut member).is_empty());
+        for _ in 0..2 {
+            member.connected();
+            assert_eq!(started(&mut member), [[OscType::Bool(true)]]);
+            assert_eq!(started(&mut member), [[OscType::Bool(false)]]);
+            assert!(started(&mut member).is_empty());
+            member.disconnected();
+        }
+    }
+}
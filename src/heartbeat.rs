@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How far ahead beats are scheduled when there are no RR intervals, about
+/// the time until the next notification.
+const APPROXIMATION_WINDOW: Duration = Duration::from_secs(1);
+/// Beats scheduled further ahead than this are dropped, so a backlog doesn't
+/// keep the toggles more and more behind the actual heart beat.
+const MAX_BACKLOG: Duration = Duration::from_secs(3);
+
+/// Schedules a toggle for every heart beat, replaying the RR intervals of
+/// each measurement or approximating them from the heart rate.
+pub struct HeartBeat {
+    pending: VecDeque<Instant>,
+    state: bool,
+}
+
+impl HeartBeat {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            state: false,
+        }
+    }
+
+    /// Cancels every scheduled toggle.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Schedules the beats of a measurement, from its RR intervals in 1/1024
+    /// seconds if it has any and from its heart rate otherwise.
+    pub fn schedule(&mut self, rr_intervals: &[u16], beats_per_minute: u8, now: Instant) {
+        let start = match self.pending.back() {
+            Some(last) if last.saturating_duration_since(now) <= MAX_BACKLOG => (*last).max(now),
+            _ => {
+                self.pending.clear();
+                now
+            }
+        };
+
+        let mut at = start;
+        if rr_intervals.is_empty() {
+            if beats_per_minute == 0 {
+                return;
+            }
+            let interval = Duration::from_secs(60) / u32::from(beats_per_minute);
+            while at < now + APPROXIMATION_WINDOW {
+                at += interval;
+                self.pending.push_back(at);
+            }
+        } else {
+            for rr_interval in rr_intervals {
+                at += Duration::from_secs_f64(f64::from(*rr_interval) / 1024.0);
+                self.pending.push_back(at);
+            }
+        }
+    }
+
+    /// When the next toggle is due, if any is scheduled.
+    pub fn next_at(&self) -> Option<Instant> {
+        self.pending.front().copied()
+    }
+
+    /// Takes the due toggle and returns the new state.
+    pub fn toggle(&mut self) -> bool {
+        self.pending.pop_front();
+        self.state = !self.state;
+        self.state
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Successive differences needed before the RMSSD means anything.
+const MIN_INTERVALS: usize = 10;
+
+/// The root mean square of successive differences of the RR intervals that
+/// add up to the latest `window`, a basic heart rate variability.
+pub struct Rmssd {
+    window: Duration,
+    capacity: usize,
+    /// In milliseconds, the oldest first.
+    intervals: VecDeque<u16>,
+}
+
+impl Rmssd {
+    /// Keeps at most `capacity` intervals, however short they are.
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity: capacity.max(MIN_INTERVALS + 1),
+            intervals: VecDeque::new(),
+        }
+    }
+
+    /// Forgets the intervals, so a gap between connections doesn't count as
+    /// one huge interval.
+    pub fn reset(&mut self) {
+        self.intervals.clear();
+    }
+
+    /// Adds RR intervals in milliseconds, dropping those that fell out of
+    /// the window.
+    pub fn extend(&mut self, rr_intervals_ms: &[u16]) {
+        self.intervals.extend(rr_intervals_ms);
+        let excess = self.intervals.len().saturating_sub(self.capacity);
+        self.intervals.drain(..excess);
+        let mut total: u64 = self
+            .intervals
+            .iter()
+            .map(|interval| u64::from(*interval))
+            .sum();
+        let window = self.window.as_millis() as u64;
+        while total > window {
+            let Some(oldest) = self.intervals.pop_front() else {
+                break;
+            };
+            total -= u64::from(oldest);
+        }
+    }
+
+    /// The RMSSD in milliseconds, `None` until there are enough intervals.
+    pub fn value(&self) -> Option<f32> {
+        if self.intervals.len() <= MIN_INTERVALS {
+            return None;
+        }
+        let (front, back) = self.intervals.as_slices();
+        let intervals: Vec<_> = front
+            .iter()
+            .chain(back)
+            .map(|interval| f64::from(*interval))
+            .collect();
+        let squares: f64 = intervals
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).powi(2))
+            .sum();
+        let mean = squares / (intervals.len() - 1) as f64;
+        Some(mean.sqrt() as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rmssd_of_alternating_intervals() {
+        let mut rmssd = Rmssd::new(Duration::from_secs(60), 3600);
+        rmssd.extend(&[800, 850, 800, 850, 800]);
+        assert_eq!(rmssd.value(), None);
+        rmssd.extend(&[850, 800, 850, 800, 850, 800]);
+        assert_eq!(rmssd.value(), Some(50.0));
+    }
+
+    #[test]
+    fn intervals_are_bounded_by_window_and_retention() {
+        let mut windowed = Rmssd::new(Duration::from_secs(10), 3600);
+        let mut retained = Rmssd::new(Duration::from_secs(3600), 20);
+        for _ in 0..10_000 {
+            windowed.extend(&[1000]);
+            retained.extend(&[1]);
+        }
+        assert_eq!(windowed.intervals.len(), 10);
+        assert_eq!(retained.intervals.len(), 20);
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use rosc::{OscMessage, OscPacket};
+use std::net::UdpSocket;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Sent by VRChat when the avatar changes, resetting its parameters.
+pub const AVATAR_CHANGE_ADDRESS: &str = "/avatar/change";
+const ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// OSC arriving at a socket, the sender address being where VRChat sends its own output.
+pub struct Inbound {
+    /// When the first packet arrived, marking VRChat as running.
+    pub detected_at: Arc<OnceLock<Instant>>,
+    pub messages: mpsc::Receiver<OscMessage>,
+}
+
+pub fn listen(socket: &UdpSocket) -> Result<Inbound> {
+    let socket = socket.try_clone()?;
+    let detected_at = Arc::new(OnceLock::new());
+    let listener_detected_at = detected_at.clone();
+    let (sender, messages) = mpsc::channel(32);
+    thread::spawn(move || {
+        let mut buffer = [0; rosc::decoder::MTU];
+        loop {
+            let size = match socket.recv_from(&mut buffer) {
+                Ok((size, _)) => size,
+                Err(error) => {
+                    info!("Failed to receive OSC: {}", error);
+                    thread::sleep(ERROR_DELAY);
+                    continue;
+                }
+            };
+            if listener_detected_at.set(Instant::now()).is_ok() {
+                info!("Detected VRChat");
+            }
+            let Ok((_, packet)) = rosc::decoder::decode_udp(&buffer[..size]) else {
+                continue;
+            };
+            for message in flatten(packet) {
+                // Messages arriving faster than they're handled are dropped.
+                if let Err(mpsc::error::TrySendError::Closed(_)) = sender.try_send(message) {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(Inbound {
+        detected_at,
+        messages,
+    })
+}
+
+fn flatten(packet: OscPacket) -> Vec<OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(flatten).collect(),
+    }
+}
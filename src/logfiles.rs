@@ -0,0 +1,122 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Whether a file name looks like one of the session logs with the given
+/// extension, like `%Y%m%d-%H%M%S.csv`.
+pub fn is_log_file_name(name: &str, extension: &str) -> bool {
+    let Some(stem) = name
+        .strip_suffix(extension)
+        .and_then(|name| name.strip_suffix('.'))
+    else {
+        return false;
+    };
+    let bytes = stem.as_bytes();
+    bytes.len() == 15
+        && bytes[8] == b'-'
+        && bytes
+            .iter()
+            .enumerate()
+            .all(|(index, byte)| index == 8 || byte.is_ascii_digit())
+}
+
+/// Returns the oldest session logs beyond the newest `max_files`, which
+/// count the `active` one of this session that is never deleted.
+pub fn files_to_delete(
+    names: &[String],
+    extension: &str,
+    max_files: usize,
+    active: &str,
+) -> Vec<String> {
+    let max_files = match is_log_file_name(active, extension) {
+        true => max_files.saturating_sub(1),
+        // A --log-file of another name isn't one of the session logs.
+        false => max_files,
+    };
+    let mut logs: Vec<_> = names
+        .iter()
+        .filter(|name| *name != active && is_log_file_name(name, extension))
+        .cloned()
+        .collect();
+    // The names sort chronologically.
+    logs.sort();
+    let excess = logs.len().saturating_sub(max_files);
+    logs.truncate(excess);
+    logs
+}
+
+/// Deletes the oldest session logs next to the `active` one beyond the
+/// newest `max_files`, counting the active one.
+pub fn clean_up(active: impl AsRef<Path>, extension: &str, max_files: usize) -> Result<()> {
+    let active = active.as_ref();
+    let directory = match active.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let active_name = active.file_name().unwrap_or_default().to_string_lossy();
+    let mut names = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            if let Ok(name) = entry.file_name().into_string() {
+                names.push(name);
+            }
+        }
+    }
+
+    for name in files_to_delete(&names, extension, max_files, &active_name) {
+        info!("Deleting old log {}", name);
+        fs::remove_file(directory.join(name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn only_session_logs_match() {
+        assert!(is_log_file_name("20260101-120000.csv", "csv"));
+        assert!(!is_log_file_name("20260101-120000.jsonl", "csv"));
+        assert!(!is_log_file_name("notes.csv", "csv"));
+        assert!(!is_log_file_name("20260101_120000.csv", "csv"));
+        assert!(!is_log_file_name("2026010-1120000.csv", "csv"));
+        assert!(!is_log_file_name("20260101-120000.csv.bak", "csv"));
+    }
+
+    #[test]
+    fn oldest_logs_beyond_limit_are_deleted() {
+        let listing = names(&[
+            "20260103-090000.csv",
+            "notes.csv",
+            "20260101-090000.csv",
+            "20260102-090000.jsonl",
+            "20260102-090000.csv",
+            "20260104-090000.csv",
+        ]);
+        assert_eq!(
+            files_to_delete(&listing, "csv", 2, "20260104-090000.csv"),
+            names(&["20260101-090000.csv", "20260102-090000.csv"])
+        );
+    }
+
+    #[test]
+    fn active_log_is_never_deleted() {
+        let listing = names(&["20260101-090000.csv", "20260102-090000.csv"]);
+        assert_eq!(
+            files_to_delete(&listing, "csv", 0, "20260102-090000.csv"),
+            names(&["20260101-090000.csv"])
+        );
+        // The active log counts toward the limit even before it exists.
+        assert_eq!(
+            files_to_delete(&listing, "csv", 2, "20260103-090000.csv"),
+            names(&["20260101-090000.csv"])
+        );
+    }
+}
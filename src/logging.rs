@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::env;
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogBackend {
+    Stderr,
+    /// Requires the journald feature
+    Journald,
+    /// Requires the syslog feature
+    Syslog,
+}
+
+/// What to log: the directives in `RUST_LOG`, like `debug` or
+/// `vrc_sa_node=trace,btleplug=warn,info`, or else info moved up by every
+/// `-v` and down by every `-q`.
+pub fn filter(verbose: u8, quiet: u8, rust_log: Option<&str>) -> Targets {
+    let directives = rust_log
+        .map(str::trim)
+        .filter(|rust_log| !rust_log.is_empty())
+        .and_then(|rust_log| rust_log.parse().ok());
+    directives.unwrap_or_else(|| Targets::new().with_default(level(verbose, quiet)))
+}
+
+fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::OFF,
+        LevelFilter::ERROR,
+        LevelFilter::WARN,
+        LevelFilter::INFO,
+        LevelFilter::DEBUG,
+        LevelFilter::TRACE,
+    ];
+    let index = (3 + usize::from(verbose)).saturating_sub(usize::from(quiet));
+    LEVELS[index.min(LEVELS.len() - 1)]
+}
+
+type BoxedSubscriber = Box<dyn Subscriber + Send + Sync>;
+
+/// Installs the global subscriber for the chosen backend.
+pub fn init(backend: LogBackend, verbose: u8, quiet: u8) -> Result<()> {
+    let filter = filter(verbose, quiet, env::var("RUST_LOG").ok().as_deref());
+    subscriber(backend, filter)?.init();
+    Ok(())
+}
+
+fn subscriber(backend: LogBackend, filter: Targets) -> Result<BoxedSubscriber> {
+    Ok(match backend {
+        LogBackend::Stderr => Box::new(
+            tracing_subscriber::registry()
+                .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+                .with(filter),
+        ),
+        LogBackend::Journald => journald_subscriber(filter)?,
+        LogBackend::Syslog => syslog_subscriber(filter)?,
+    })
+}
+
+#[cfg(feature = "journald")]
+fn journald_subscriber(filter: Targets) -> Result<BoxedSubscriber> {
+    Ok(Box::new(
+        tracing_subscriber::registry()
+            .with(tracing_journald::layer()?)
+            .with(filter),
+    ))
+}
+
+#[cfg(not(feature = "journald"))]
+fn journald_subscriber(_filter: Targets) -> Result<BoxedSubscriber> {
+    bail!("Logging to journald requires building with the journald feature")
+}
+
+#[cfg(all(feature = "syslog", unix))]
+fn syslog_subscriber(filter: Targets) -> Result<BoxedSubscriber> {
+    use syslog_tracing::Syslog;
+
+    let identity = c"vrc_sa_node";
+    let (options, facility) = Default::default();
+    let Some(syslog) = Syslog::new(identity, options, facility) else {
+        bail!("Failed to open syslog");
+    };
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(syslog)
+        .with_ansi(false)
+        .without_time();
+    Ok(Box::new(
+        tracing_subscriber::registry().with(layer).with(filter),
+    ))
+}
+
+#[cfg(not(all(feature = "syslog", unix)))]
+fn syslog_subscriber(_filter: Targets) -> Result<BoxedSubscriber> {
+    bail!("Logging to syslog requires building with the syslog feature on a Unix platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+
+    #[test]
+    fn flags_move_the_level_from_info() {
+        assert_eq!(filter(0, 0, None).default_level(), Some(LevelFilter::INFO));
+        assert_eq!(filter(1, 0, None).default_level(), Some(LevelFilter::DEBUG));
+        assert_eq!(filter(5, 0, None).default_level(), Some(LevelFilter::TRACE));
+        assert_eq!(filter(0, 1, None).default_level(), Some(LevelFilter::WARN));
+        assert_eq!(filter(0, 9, None).default_level(), Some(LevelFilter::OFF));
+    }
+
+    #[test]
+    fn rust_log_directives_override_the_flags() {
+        let filter = filter(0, 1, Some("vrc_sa_node=trace,btleplug=warn,info"));
+        assert!(filter.would_enable("vrc_sa_node::device", &Level::TRACE));
+        assert!(!filter.would_enable("btleplug::bluez", &Level::INFO));
+        assert!(filter.would_enable("tokio", &Level::INFO));
+        assert!(!filter.would_enable("tokio", &Level::DEBUG));
+    }
+
+    #[test]
+    fn falls_back_to_the_flags_without_usable_rust_log() {
+        for rust_log in ["", " ", "vrc_sa_node=loud"] {
+            let filter = filter(1, 0, Some(rust_log));
+            assert_eq!(filter.default_level(), Some(LevelFilter::DEBUG));
+        }
+    }
+
+    #[test]
+    fn subscribers_are_built_for_the_enabled_backends() {
+        // Without a journal to connect to, journald fails for another reason.
+        let missing_feature = |backend| match subscriber(backend, filter(0, 0, None)) {
+            Ok(_) => false,
+            Err(error) => error.to_string().contains("requires building"),
+        };
+        assert!(subscriber(LogBackend::Stderr, filter(0, 0, None)).is_ok());
+        assert_eq!(
+            missing_feature(LogBackend::Journald),
+            !cfg!(feature = "journald")
+        );
+        assert_eq!(
+            missing_feature(LogBackend::Syslog),
+            !cfg!(all(feature = "syslog", unix))
+        );
+        #[cfg(all(feature = "syslog", unix))]
+        assert!(subscriber(LogBackend::Syslog, filter(0, 0, None)).is_ok());
+    }
+}
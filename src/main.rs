@@ -1,300 +1,1927 @@
-use anyhow::Result;
-use async_trait::async_trait;
-use btleplug::api::{
-    BDAddr, Central, Manager as _, Peripheral as _, ScanFilter, ValueNotification,
-};
-use btleplug::platform::{Adapter, Manager, Peripheral};
+mod age;
+mod backoff;
+mod calories;
+mod capture;
+mod config;
+mod contact;
+mod device;
+mod effort;
+mod energy;
+mod failover;
+mod features;
+#[cfg(unix)]
+mod fifo;
+mod gauge;
+mod group;
+mod heartbeat;
+mod hrv;
+mod inbound;
+mod logfiles;
+mod logging;
+mod measurement;
+mod merge;
+mod metrics;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod normalize;
+mod osc;
+mod passive;
+mod peripheral;
+mod presence;
+mod preset;
+mod prompt;
+mod pulse;
+mod recording;
+mod remember;
+mod rr;
+mod sample;
+mod simulate;
+mod sink;
+mod smoothing;
+mod spike;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod sse;
+mod status;
+mod stronger;
+mod summary;
+mod sustained;
+mod telemetry;
+mod template;
+mod timing;
+mod zone;
+
+use anyhow::{anyhow, bail, Context, Result};
+use backoff::Backoff;
+use btleplug::api::{BDAddr, Central, Manager as _};
+use btleplug::platform::{Adapter, Manager};
+use calories::Sex;
+use capture::CaptureWriter;
 use chrono::prelude::Local;
-use clap::Parser;
-use csv::Writer;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
+use contact::ContactFilter;
+use csv::{QuoteStyle, Writer};
+use device::{Device, DeviceEvent, DeviceOptions};
 use dialoguer::{theme::ColorfulTheme, Select};
+use energy::EnergyAccumulator;
+use failover::{Failover, Role};
+use features::OscFeature;
 use futures::future::join_all;
-use futures::{Stream, StreamExt};
-use rosc::{encoder, OscMessage, OscPacket, OscType};
+use group::GroupMember;
+use logging::LogBackend;
+use measurement::{HeartRateMeasurement, Notification};
+use merge::{MergeMode, Merger};
+use osc::{OscSender, OutputClamp, RetryPolicy};
+use peripheral::{
+    AdapterExt, ConnectOptions, ConnectionInterval, LinkRequest, PeripheralTarget,
+    BATTERY_LEVEL_CHARACTERISTIC_UUID, CONNECTION_PARAMETER_REQUESTS_SUPPORTED,
+    HEART_RATE_CHARACTERISTIC_UUID, MTU_REQUESTS_SUPPORTED,
+};
+use presence::LaunchGate;
+use preset::{HeartRateParameters, Preset};
+use prompt::Cancelled;
+use recording::RecordingStats;
+use rosc::OscType;
+use rr::RrAvailability;
+use sample::Sample;
+use serde::Serialize;
+use sink::{CsvSink, JsonlSink, LogFormat, LogSink, StdoutSink};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::net::UdpSocket;
-use std::pin::Pin;
-use std::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use stronger::StrongerPeripheralTracker;
+use summary::SessionStats;
+use telemetry::{SessionState, TelemetryOptions};
+use template::StringTemplate;
+use timing::TimingReport;
+use tokio::sync::mpsc;
 use tokio::time;
-use tokio::time::timeout;
-use tracing::info;
-use uuid::{uuid, Uuid};
+use tracing::{info, warn};
+use uuid::Uuid;
 
-const BATTERY_LEVEL_CHARACTERISTIC_UUID: Uuid = uuid!("00002a19-0000-1000-8000-00805f9b34fb");
-const HEART_RATE_CHARACTERISTIC_UUID: Uuid = uuid!("00002a37-0000-1000-8000-00805f9b34fb");
+const PRIMARY_DEVICE: usize = 0;
+const BACKUP_DEVICE: usize = 1;
+/// The device of the second --peripheral-address, the others follow it.
+const FIRST_GROUP_DEVICE: usize = 2;
+const DEFAULT_SENDER_PORT: u16 = 9001;
 
-#[async_trait]
-trait AdapterExt {
-    async fn scan_for(&self, seconds: u64) -> Result<()>;
-    async fn scan_for_peripheral(&self, address: BDAddr) -> Result<Peripheral>;
-}
+const STRONGER_PERIPHERAL_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const GAUGE_TICK_INTERVAL: Duration = Duration::from_millis(100);
+const SEARCHING_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const RR_WARMUP: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Serialize, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Arguments {
+    /// Peripheral address, repeat to stream from several peripherals into HeartRate1,
+    /// HeartRate2 and so on
+    #[arg(short, long)]
+    peripheral_address: Vec<String>,
 
-#[async_trait]
-impl AdapterExt for Adapter {
-    async fn scan_for(&self, seconds: u64) -> Result<()> {
-        let filter = ScanFilter::default();
-        let duration = Duration::from_secs(seconds);
+    /// Peripheral name, matched case-insensitively against part of the local name
+    #[arg(long)]
+    peripheral_name: Option<String>,
 
-        self.start_scan(filter).await?;
-        time::sleep(duration).await;
-        self.stop_scan().await?;
+    /// Index of the peripheral to connect to, in the order of the interactive menu
+    #[arg(long, conflicts_with_all = ["peripheral_address", "peripheral_name", "passive"])]
+    peripheral_index: Option<usize>,
 
-        Ok(())
-    }
+    /// Don't remember the peripheral connected to, or connect to the remembered one
+    #[arg(long)]
+    no_remember: bool,
 
-    async fn scan_for_peripheral(&self, address: BDAddr) -> Result<Peripheral> {
-        info!("Scanning for peripheral with address {}", address);
+    /// Bluetooth adapter to use, by index or by part of its name, instead of asking
+    #[arg(long)]
+    adapter: Option<String>,
 
-        let filter = ScanFilter::default();
-        let duration = Duration::from_secs(1);
+    /// Times to retry getting the Bluetooth adapters while Bluetooth isn't available
+    #[arg(long, default_value_t = 5)]
+    adapter_init_retries: u32,
 
-        self.start_scan(filter).await?;
-        let peripheral = loop {
-            time::sleep(duration).await;
-            let peripherals = self.peripherals().await?;
-            let maybe_peripheral = peripherals
-                .iter()
-                .find(|peripheral| peripheral.address() == address);
-            match maybe_peripheral {
-                Some(peripheral) => break peripheral.clone(),
-                None => continue,
-            }
-        };
-        self.stop_scan().await?;
+    /// Receiver addresses, comma separated or repeated
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "127.0.0.1:9000",
+        value_parser = parse_socket_address
+    )]
+    receiver: Vec<String>,
+
+    /// Sender address, 127.0.0.1:9001 or [::1]:9001 for IPv6 receivers, or any free port
+    /// when that is taken
+    #[arg(long, value_parser = parse_socket_address)]
+    sender: Option<String>,
+
+    /// Timeout threshold
+    #[arg(short, long, default_value_t = 5)]
+    timeout_threshold: u64,
+
+    /// MTU to request after connecting, where the platform supports it
+    #[arg(long)]
+    mtu: Option<u16>,
+
+    /// Connection interval to request after connecting, where supported
+    #[arg(long, value_enum)]
+    conn_interval: Option<ConnectionInterval>,
+
+    /// File to capture raw notifications to
+    #[arg(long)]
+    capture: Option<String>,
+
+    /// Heart rate below which the wearer is considered calm
+    #[arg(long)]
+    calm_bpm: Option<u16>,
+
+    /// Seconds the heart rate has to stay below --calm-bpm to be considered calm
+    #[arg(long, default_value_t = 60)]
+    calm_duration: u64,
+
+    /// Write the effective configuration to a TOML file and exit
+    #[arg(long)]
+    #[serde(skip)]
+    dump_config: Option<String>,
+
+    /// Scan for --scan-duration seconds, print the peripherals found and exit
+    #[arg(long)]
+    #[serde(skip)]
+    list_devices: bool,
+
+    /// TOML file of defaults for the other options, ~/.config/vrc_sa_node/config.toml if it exists
+    #[arg(long)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// Port to serve server-sent events on
+    #[arg(long)]
+    sse_port: Option<u16>,
+
+    /// Port to serve the current heart rate, battery level and connection state on as JSON
+    #[arg(long)]
+    http_port: Option<u16>,
+
+    /// Port to serve Prometheus metrics on at /metrics
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Milliseconds to wait between discovering services and subscribing
+    #[arg(long, default_value_t = 0)]
+    pre_subscribe_delay: u64,
+
+    /// Switch to a peripheral matching --peripheral-name that is sustainedly stronger
+    #[arg(long, requires = "peripheral_name")]
+    prefer_stronger: bool,
+
+    /// How many dBm stronger a peripheral has to be for --prefer-stronger
+    #[arg(long, default_value_t = 10)]
+    prefer_stronger_margin: i16,
+
+    /// Seconds a peripheral has to stay stronger for --prefer-stronger
+    #[arg(long, default_value_t = 10)]
+    prefer_stronger_duration: u64,
+
+    /// Heart rate the HeartRate float reaches 1.0 at
+    #[arg(long, default_value_t = 200)]
+    max_heart_rate: u16,
+
+    /// Maximum heart rate the HeartRateZone bands are fractions of, enabling them
+    #[arg(long)]
+    zone_max: Option<u16>,
+
+    /// Heart rate the HeartRate float is 0.0 at
+    #[arg(long, default_value_t = 0)]
+    min_heart_rate: u16,
+
+    /// Heart rate parameters of an avatar base: default, hrtovrchat or vrcosc
+    #[arg(long, default_value_t = String::from("default"))]
+    preset: String,
+
+    /// Address the HeartRate parameters are named after, HeartRateInt becoming <prefix>Int
+    #[arg(long, default_value_t = String::from("/avatar/parameters/HeartRate"))]
+    osc_prefix: String,
+
+    /// Prefix of the --preset parameter addresses, instead of the preset's own
+    #[arg(long)]
+    address_prefix: Option<String>,
+
+    /// Normalized value to send right after connecting, until the first sample arrives
+    #[arg(long, value_parser = parse_unit_interval)]
+    init_value: Option<f32>,
+
+    /// Weight of the previous heart rate in an average of what OSC sends, below 1.0, 0 for none
+    #[arg(long, value_parser = parse_smoothing)]
+    smoothing: Option<f32>,
+
+    /// Milliseconds over which the HeartRate float eases toward each new value
+    #[arg(long)]
+    gauge_ease_ms: Option<u64>,
+
+    /// Backup peripheral address, used when the primary peripheral fails
+    #[arg(long)]
+    backup_address: Option<String>,
+
+    /// Backup peripheral name, used when the primary peripheral fails
+    #[arg(long)]
+    backup_name: Option<String>,
+
+    /// Reconnection attempts the primary peripheral may fail in a row before the backup takes
+    /// over, the primary then keeps reconnecting to take over again
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    backup_after: u32,
+
+    /// Milliseconds below which notifications following each other are dropped
+    #[arg(long, default_value_t = 0)]
+    min_notify_interval_ms: u64,
+
+    /// Record for the given number of seconds without sending OSC, print statistics and exit
+    #[arg(long)]
+    record_and_exit: Option<u64>,
+
+    /// JSON file to write a summary of the session to at shutdown
+    #[arg(long)]
+    run_summary: Option<String>,
+
+    /// Capture to run through the pipeline instead of connecting to a peripheral
+    #[arg(
+        long,
+        conflicts_with_all = ["passive", "prefer_stronger", "backup_address", "backup_name"]
+    )]
+    reprocess: Option<String>,
+
+    /// Reprocess the capture as fast as possible instead of at its original pace
+    #[arg(long, requires = "reprocess")]
+    reprocess_fast: bool,
+
+    /// Send a synthetic heart rate instead of connecting to a peripheral
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "reprocess",
+            "passive",
+            "prefer_stronger",
+            "backup_address",
+            "backup_name",
+        ]
+    )]
+    simulate: bool,
+
+    /// Heart rate for --simulate to send, instead of one walking between 60 and 160
+    #[arg(long, requires = "simulate")]
+    simulate_bpm: Option<u8>,
+
+    /// Seconds to hold OSC back after VRChat is first detected sending to the sender address,
+    /// or to --osc-listen
+    #[arg(long)]
+    post_launch_delay: Option<u64>,
+
+    /// Address at the sender, or at --osc-listen, to pause logging with, true or 1 pausing and
+    /// false or 0 resuming
+    #[arg(long)]
+    pause_log_address: Option<String>,
+
+    /// Address to receive VRChat's OSC on, to send the heart rate, battery, connection and zone
+    /// again when the avatar changes
+    #[arg(long, value_parser = parse_socket_address)]
+    osc_listen: Option<String>,
+
+    /// Address to additionally send the heart rate to as a string
+    #[arg(long)]
+    string_output: Option<String>,
+
+    /// Address to also send the heart rate, normalized heart rate and battery level to,
+    /// as an int and two floats in one message
+    #[arg(long)]
+    combined_message: Option<String>,
+
+    /// Template for --string-output, with {bpm} and {battery} placeholders
+    #[arg(long, default_value_t = String::from("{bpm} bpm"))]
+    string_template: String,
+
+    /// Field delimiter of the CSV log, a single ASCII character
+    #[arg(long, default_value_t = ',', value_parser = parse_csv_delimiter)]
+    csv_delimiter: char,
+
+    /// When to quote fields of the CSV log
+    #[arg(long, value_enum, default_value_t = CsvQuoting::Necessary)]
+    csv_quoting: CsvQuoting,
+
+    /// Normalize the HeartRate float against the 5th to 95th percentile of recent heart rates
+    #[arg(long)]
+    percentile_range: bool,
+
+    /// Number of recent heart rates --percentile-range considers
+    #[arg(long, default_value_t = 300)]
+    percentile_window: usize,
+
+    /// How to combine the primary and backup peripherals, separate fails over between them
+    #[arg(long, value_enum, default_value_t = MergeMode::Separate)]
+    merge_mode: MergeMode,
+
+    /// Times to retry an OSC send that failed with a transient error
+    #[arg(long, default_value_t = 2)]
+    osc_retries: u32,
+
+    /// Milliseconds to wait before retrying an OSC send
+    #[arg(long, default_value_t = 5)]
+    osc_retry_delay_ms: u64,
+
+    /// Send each OSC message on its own instead of bundling every update
+    #[arg(long)]
+    no_bundle: bool,
+
+    /// Lowest value sent for the parameters in [0.0, 1.0], like HeartRate and Battery
+    #[arg(long, default_value_t = 0.0)]
+    output_clamp_min: f32,
+
+    /// Highest value sent for the parameters in [0.0, 1.0], like HeartRate and Battery
+    #[arg(long, default_value_t = 1.0)]
+    output_clamp_max: f32,
+
+    /// Ignore readings taken without sensor contact, if the peripheral reports contact
+    #[arg(long)]
+    require_contact: bool,
+
+    /// Lowest heart rate taken as a real reading, lower ones aren't sent
+    #[arg(long, default_value_t = 30)]
+    min_valid_bpm: u16,
+
+    /// Highest heart rate taken as a real reading, higher ones aren't sent
+    #[arg(long, default_value_t = 230)]
+    max_valid_bpm: u16,
+
+    /// Leave readings outside --min-valid-bpm and --max-valid-bpm out of the logs too
+    #[arg(long)]
+    drop_invalid_bpm: bool,
+
+    /// Read heart rate from advertisements instead of connecting to the peripheral, which counts
+    /// as disconnected after --timeout-threshold seconds without any
+    #[arg(long, conflicts_with_all = ["prefer_stronger", "backup_address", "backup_name"])]
+    passive: bool,
+
+    /// Resting heart rate of the wearer
+    #[arg(long)]
+    resting_hr: Option<u8>,
+
+    /// Maximum heart rate of the wearer
+    #[arg(long)]
+    max_hr: Option<u8>,
+
+    /// Send an experimental, approximate effort index (not medical advice)
+    #[arg(long, requires_all = ["resting_hr", "max_hr"])]
+    effort_index: bool,
+
+    /// Send HeartRateDelta, the rise above --resting-hr as a fraction of this many bpm
+    #[arg(long, requires = "resting_hr", value_parser = clap::value_parser!(u8).range(1..))]
+    baseline_delta: Option<u8>,
+
+    /// SQLite database to log samples to, in addition to the CSV
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    sqlite: Option<String>,
+
+    /// Restart smoothing from the first sample after a reconnect
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    reset_smoothing_on_reconnect: bool,
+
+    /// Milliseconds to wait between interactive scans
+    #[arg(long, default_value_t = 500)]
+    scan_interval_ms: u64,
+
+    /// Seconds each scan listens for advertisements before listing peripherals
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    scan_duration: u64,
+
+    /// Seconds to look for --peripheral-address or --peripheral-name before giving up
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    scan_timeout: Option<u64>,
+
+    /// Leave peripherals that weren't seen in the latest interactive scan out of the menu
+    #[arg(long)]
+    drop_stale_peripherals: bool,
+
+    /// List every peripheral in the menu, not only those advertising the heart rate service
+    #[arg(long)]
+    show_all: bool,
+
+    /// Number of times to retry discovering the services of a peripheral
+    #[arg(long, default_value_t = 2)]
+    discovery_retries: u32,
+
+    /// Milliseconds to wait before retrying service discovery
+    #[arg(long, default_value_t = 500)]
+    discovery_retry_delay_ms: u64,
+
+    /// Failed connection attempts in a row after which to exit, instead of retrying forever
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_reconnect_attempts: Option<u32>,
+
+    /// UUID of the characteristic to read the heart rate from, for sensors that don't use the
+    /// standard one
+    #[arg(long, value_parser = parse_uuid)]
+    hr_characteristic: Option<String>,
+
+    /// UUID of the characteristic to read the battery level from, for sensors that don't use
+    /// the standard one
+    #[arg(long, value_parser = parse_uuid)]
+    battery_characteristic: Option<String>,
+
+    /// Named pipe to write samples to as JSON lines, created if it doesn't exist
+    #[cfg(unix)]
+    #[arg(long)]
+    fifo: Option<String>,
+
+    /// Print a compact JSON object per sample to stdout, the logs staying on stderr
+    #[arg(long)]
+    json_stdout: bool,
+
+    /// Formats to log samples in, each to its own file
+    #[arg(long, value_enum, default_values_t = [LogFormat::Csv])]
+    log_format: Vec<LogFormat>,
+
+    /// File to log to instead of a new timestamped one, other formats replace its extension
+    #[arg(long)]
+    log_file: Option<String>,
 
-        info!("Peripheral with address {} found", address);
+    /// Append to --log-file instead of overwriting it
+    #[arg(long, requires = "log_file")]
+    log_append: bool,
 
-        Ok(peripheral)
+    /// Don't log samples to files at all
+    #[arg(long, conflicts_with_all = ["log_file", "max_log_files"])]
+    no_log: bool,
+
+    /// Maximum number of logs of each format to keep next to the log of this session, counting
+    /// it, deleting the oldest at startup
+    #[arg(long)]
+    max_log_files: Option<usize>,
+
+    /// Rise in bpm above the smoothed baseline that triggers HeartRateSpike
+    #[arg(long)]
+    spike_delta: Option<f32>,
+
+    /// Pulse HeartRateReconnected after reconnecting to the peripheral
+    #[arg(long)]
+    reconnect_cue: bool,
+
+    /// Pulse HeartRateStarted with the first sample after each connection
+    #[arg(long)]
+    started_cue: bool,
+
+    /// Toggle HeartRateBeat with every heart beat, from the RR intervals or the heart rate
+    #[arg(long)]
+    heart_beat: bool,
+
+    /// Send exactly these outputs, instead of enabling them one flag at a time
+    #[arg(long, value_enum, value_delimiter = ',')]
+    features: Option<Vec<OscFeature>>,
+
+    /// Send HeartRateAge, the seconds since the last sample, every this many milliseconds
+    #[arg(long)]
+    age_interval_ms: Option<u64>,
+
+    /// Seconds HeartRateAge is normalized against, sending [0.0, 1.0] instead
+    #[arg(long, requires = "age_interval_ms")]
+    age_max: Option<f32>,
+
+    /// Milliseconds without a sample after which HeartRateSearching is sent as true
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    searching_window: Option<u64>,
+
+    /// Seconds of RR intervals to compute HeartRateHRV from, as their RMSSD
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    hrv_window: Option<u64>,
+
+    /// RMSSD in milliseconds that HeartRateHRV is normalized against, sending [0.0, 1.0]
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    hrv_max: u16,
+
+    /// Milliseconds without a sample after which the last heart rate, battery level and
+    /// Connected are sent again, until --timeout-threshold is reached
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    keepalive_interval: Option<u64>,
+
+    /// Seconds between checks that the peripheral is still connected
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    health_poll_interval: Option<u64>,
+
+    /// Send every parsed measurement packed into the HeartRateDebug string
+    #[arg(long)]
+    debug_osc: bool,
+
+    /// Maximum number of samples any rolling window keeps in memory
+    #[arg(long, default_value_t = 3600)]
+    retention: usize,
+
+    /// Don't discover or read the battery level characteristic at all
+    #[arg(long)]
+    skip_battery: bool,
+
+    /// Minutes between battery level reads, instead of reading it once per connection
+    #[arg(
+        long,
+        conflicts_with = "skip_battery",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    battery_interval: Option<u64>,
+
+    /// Where to write log messages
+    #[arg(long, value_enum, default_value_t = LogBackend::Stderr)]
+    log_backend: LogBackend,
+
+    /// Log more, debug once and trace twice, unless RUST_LOG sets directives like btleplug=debug
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less, only warnings once and only errors twice
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
+
+    /// Age of the wearer in years
+    #[arg(long)]
+    age: Option<u8>,
+
+    /// Weight of the wearer in kilograms
+    #[arg(long)]
+    weight_kg: Option<f32>,
+
+    /// Sex of the wearer
+    #[arg(long, value_enum)]
+    sex: Option<Sex>,
+
+    /// Send an approximate count of kilocalories burned (not medical advice)
+    #[arg(long, requires_all = ["age", "weight_kg", "sex"])]
+    calories: bool,
+
+    /// Print histograms of notification and RR intervals when shutting down
+    #[arg(long)]
+    timing_report: bool,
+
+    /// Seconds to observe the heart rate for, then normalize against the range seen
+    #[arg(long, conflicts_with = "percentile_range")]
+    auto_seed_range: Option<u64>,
+
+    /// Reset the energy expended counter of the peripheral before it wraps around
+    #[arg(long)]
+    reset_energy_near_overflow: bool,
+
+    /// MQTT broker to publish samples to, like mqtt://localhost:1883
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_topic")]
+    mqtt_url: Option<String>,
+
+    /// MQTT topic to publish samples to
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_url")]
+    mqtt_topic: Option<String>,
+}
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum CsvQuoting {
+    Necessary,
+    Always,
+    NonNumeric,
+    Never,
+}
+
+impl From<CsvQuoting> for QuoteStyle {
+    fn from(quoting: CsvQuoting) -> Self {
+        match quoting {
+            CsvQuoting::Necessary => QuoteStyle::Necessary,
+            CsvQuoting::Always => QuoteStyle::Always,
+            CsvQuoting::NonNumeric => QuoteStyle::NonNumeric,
+            CsvQuoting::Never => QuoteStyle::Never,
+        }
     }
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Arguments {
-    /// Peripheral address
-    #[arg(short, long)]
-    peripheral_address: Option<String>,
+fn parse_csv_delimiter(delimiter: &str) -> Result<char, String> {
+    let mut characters = delimiter.chars();
+    match (characters.next(), characters.next()) {
+        (Some(character), None) if character.is_ascii() => Ok(character),
+        _ => Err(String::from("expected a single ASCII character")),
+    }
+}
 
-    /// Receiver address
-    #[arg(short, long, default_value_t = String::from("127.0.0.1:9000"))]
-    receiver: String,
+fn parse_unit_interval(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(String::from("expected a number from 0.0 to 1.0")),
+    }
+}
 
-    /// Sender address
-    #[arg(long, default_value_t = String::from("127.0.0.1:9001"))]
-    sender: String,
+/// Checks for a host and a port, which are only resolved once everything is
+/// parsed.
+fn parse_socket_address(value: &str) -> Result<String, String> {
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(String::from(value))
+        }
+        _ => Err(String::from(
+            "expected a host and a port like 127.0.0.1:9000, localhost:9000 or [::1]:9000",
+        )),
+    }
+}
 
-    /// Timeout threshold
-    #[arg(short, long, default_value_t = 5)]
-    timeout_threshold: u64,
+fn parse_uuid(value: &str) -> Result<String, String> {
+    match Uuid::parse_str(value) {
+        Ok(_) => Ok(String::from(value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The characteristic given on the command line, already checked by
+/// [`parse_uuid`], or the standard one.
+fn characteristic(uuid: Option<&str>, standard: Uuid) -> Uuid {
+    uuid.and_then(|uuid| Uuid::parse_str(uuid).ok())
+        .unwrap_or(standard)
+}
+
+fn parse_smoothing(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(value) if (0.0..1.0).contains(&value) => Ok(value),
+        _ => Err(String::from("expected a number from 0.0 up to 1.0")),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt::init();
+    let arguments = parse_arguments()?;
+    logging::init(arguments.log_backend, arguments.verbose, arguments.quiet)?;
 
-    let arguments = Arguments::parse();
-    let socket = UdpSocket::bind(&arguments.sender).unwrap();
-    info!("Binded to address {}", arguments.sender);
+    match run(arguments).await {
+        Err(error) if error.is::<Cancelled>() => {
+            info!("{}", error);
+            Ok(())
+        }
+        result => result.map_err(Into::into),
+    }
+}
 
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let adapter = if adapters.len() == 1 {
-        adapters.first().unwrap()
-    } else {
-        let adpater_selection_items = join_all(
-            adapters
-                .iter()
-                .map(|adapter| async { format!("{:?}", adapter.adapter_info().await.unwrap()) })
-                .collect::<Vec<_>>(),
-        )
-        .await;
-        let adapter_selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select bluetooth adapter")
-            .default(0)
-            .items(&adpater_selection_items)
-            .interact()
-            .unwrap();
-        adapters.get(adapter_selection).unwrap()
+/// Parses the command line on top of the configuration file, if there is one.
+fn parse_arguments() -> Result<Arguments> {
+    // Only looks for --config and which options are given, the command line
+    // on its own may well not be complete.
+    let matches = Arguments::command().ignore_errors(true).get_matches();
+    let path = matches
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .or_else(config::default_path);
+    let Some(path) = path else {
+        return Ok(Arguments::parse());
     };
+    let file_arguments = config::file_arguments(&Arguments::command(), &matches, &path)?;
+    let mut command_line = env::args_os();
+    let program = command_line.next();
+    Ok(Arguments::parse_from(
+        program
+            .into_iter()
+            .chain(file_arguments)
+            .chain(command_line),
+    ))
+}
 
-    // If the user passed a peripheral address, try to parse it.
-    let maybe_peripheral_address = arguments.peripheral_address.and_then(|peripheral_address| {
-        let delimiter = BDAddr::from_str_delim(&peripheral_address);
-        let no_delimiter = BDAddr::from_str_no_delim(&peripheral_address);
-        delimiter.or(no_delimiter).ok()
-    });
+async fn run(arguments: Arguments) -> Result<()> {
+    if let Some(path) = &arguments.dump_config {
+        std::fs::write(path, toml::to_string(&arguments)?)?;
+        info!("Wrote configuration to {}", path);
+        return Ok(());
+    }
+
+    if arguments.list_devices {
+        let adapter =
+            select_adapter(arguments.adapter.as_deref(), arguments.adapter_init_retries).await?;
+        let duration = Duration::from_secs(arguments.scan_duration);
+        let peripherals = peripheral::list_peripherals(&adapter, duration).await?;
+        print!("{}", peripheral::device_table(&peripherals));
+        return Ok(());
+    }
+
+    let outputs = resolve_outputs(&arguments)?;
+    if arguments.min_heart_rate >= arguments.max_heart_rate {
+        bail!("--min-heart-rate must be lower than --max-heart-rate");
+    }
+    if arguments.min_valid_bpm > arguments.max_valid_bpm {
+        bail!("--min-valid-bpm can't be higher than --max-valid-bpm");
+    }
+    let valid_bpm = arguments.min_valid_bpm..=arguments.max_valid_bpm;
+    let preset = preset::find(&arguments.preset)?;
+    // With several peripherals every one of them gets numbered parameters,
+    // HeartRate1 for the first.
+    let grouped = arguments.peripheral_address.len() > 1;
+    if grouped {
+        if preset.prefix.is_some() || arguments.address_prefix.is_some() {
+            bail!("Several peripherals only have numbered parameters with the default preset");
+        }
+        if arguments.backup_address.is_some() || arguments.backup_name.is_some() {
+            bail!("Several peripherals can't be combined with a backup peripheral");
+        }
+        if arguments.reprocess.is_some() || arguments.simulate {
+            bail!("Several peripherals can't be reprocessed or simulated");
+        }
+    }
+    let osc_prefix = match grouped {
+        true => format!("{}1", arguments.osc_prefix),
+        false => arguments.osc_prefix.clone(),
+    };
+    let parameter = |suffix: &str| format!("{}{}", osc_prefix, suffix);
+    let string_output = match &arguments.string_output {
+        Some(address) => {
+            osc::validate_address(address)?;
+            let template = StringTemplate::parse(&arguments.string_template)?;
+            Some((address.clone(), template))
+        }
+        None => None,
+    };
+    osc::validate_address(&arguments.osc_prefix)?;
+    for address in [
+        &arguments.combined_message,
+        &arguments.pause_log_address,
+        &arguments.address_prefix,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        osc::validate_address(address)?;
+    }
+
+    let mut receivers = vec![];
+    for receiver in &arguments.receiver {
+        receivers.push(osc::resolve(receiver).await?);
+    }
+    let sender = match &arguments.sender {
+        Some(sender) => Some(osc::resolve(sender).await?),
+        None => None,
+    };
+    // Bound first, so the sender falls back to a free port when both use
+    // VRChat's output port.
+    let listen_socket = match &arguments.osc_listen {
+        Some(address) => {
+            let address = osc::resolve(address).await?;
+            let socket = UdpSocket::bind(address)
+                .with_context(|| format!("Failed to listen for OSC on {}", address))?;
+            info!("Listening for OSC on {}", address);
+            Some(socket)
+        }
+        None => None,
+    };
+    let socket = bind_sender(sender, &receivers)?;
+    // VRChat's OSC is received on --osc-listen when it's given, or else at
+    // the sender address it sends to by default.
+    let listen = arguments.post_launch_delay.is_some() || arguments.pause_log_address.is_some();
+    let mut inbound = match &listen_socket {
+        Some(listen_socket) => Some(inbound::listen(listen_socket)?),
+        None => listen.then(|| inbound::listen(&socket)).transpose()?,
+    };
+    let launch_gate = match (arguments.post_launch_delay, &inbound) {
+        (Some(seconds), Some(inbound)) => Some(LaunchGate::new(
+            Duration::from_secs(seconds),
+            inbound.detected_at.clone(),
+        )),
+        _ => None,
+    };
+    let retry_policy = RetryPolicy {
+        retries: arguments.osc_retries,
+        delay: Duration::from_millis(arguments.osc_retry_delay_ms),
+    };
+    let (min, max) = (arguments.output_clamp_min, arguments.output_clamp_max);
+    if min.is_nan() || max.is_nan() || min > max {
+        bail!("The output clamp range {} to {} is empty", min, max);
+    }
+    let mut osc = OscSender::new(
+        socket,
+        receivers,
+        launch_gate,
+        retry_policy,
+        OutputClamp::new(min, max, arguments.combined_message.clone()),
+        !arguments.no_bundle,
+    );
+
+    // Reprocessing a capture or simulating doesn't need Bluetooth at all.
+    let adapter = if arguments.reprocess.is_some() || arguments.simulate {
+        None
+    } else {
+        Some(select_adapter(arguments.adapter.as_deref(), arguments.adapter_init_retries).await?)
+    };
 
     let threshold = Duration::from_secs(arguments.timeout_threshold);
-    let mut connected_peripheral = connect_to_peripheral(adapter, maybe_peripheral_address).await?;
-    let mut writer = get_log_writer()?;
+    let device_options = DeviceOptions {
+        connect: ConnectOptions {
+            mtu: match peripheral::link_request(arguments.mtu, MTU_REQUESTS_SUPPORTED) {
+                LinkRequest::Request(mtu) => Some(mtu),
+                LinkRequest::Unsupported => {
+                    warn!("Ignoring --mtu, this platform negotiates the MTU itself");
+                    None
+                }
+                LinkRequest::Skip => None,
+            },
+            connection_interval: match peripheral::link_request(
+                arguments.conn_interval,
+                CONNECTION_PARAMETER_REQUESTS_SUPPORTED,
+            ) {
+                LinkRequest::Request(connection_interval) => Some(connection_interval),
+                LinkRequest::Unsupported => {
+                    warn!("Ignoring --conn-interval, this platform picks the connection interval");
+                    None
+                }
+                LinkRequest::Skip => None,
+            },
+            pre_subscribe_delay: Duration::from_millis(arguments.pre_subscribe_delay),
+            keep_scanning: arguments.prefer_stronger,
+            scan_interval: Duration::from_millis(arguments.scan_interval_ms),
+            scan_duration: Duration::from_secs(arguments.scan_duration),
+            scan_timeout: arguments.scan_timeout.map(Duration::from_secs),
+            skip_battery: arguments.skip_battery,
+            drop_stale: arguments.drop_stale_peripherals,
+            show_all: arguments.show_all,
+            discovery_retries: arguments.discovery_retries,
+            discovery_retry_delay: Duration::from_millis(arguments.discovery_retry_delay_ms),
+            max_reconnect_attempts: arguments.max_reconnect_attempts,
+            heart_rate_characteristic: characteristic(
+                arguments.hr_characteristic.as_deref(),
+                HEART_RATE_CHARACTERISTIC_UUID,
+            ),
+            battery_characteristic: characteristic(
+                arguments.battery_characteristic.as_deref(),
+                BATTERY_LEVEL_CHARACTERISTIC_UUID,
+            ),
+        },
+        threshold,
+        min_notify_interval: Duration::from_millis(arguments.min_notify_interval_ms),
+        passive: arguments.passive,
+        health_poll_interval: arguments.health_poll_interval.map(Duration::from_secs),
+        battery_interval: arguments
+            .battery_interval
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+        failover_after: None,
+    };
+    let target = match arguments.peripheral_index {
+        Some(index) => {
+            warn!("Peripheral indices aren't stable across scans or runs");
+            PeripheralTarget::Index(index)
+        }
+        None => peripheral_target(
+            arguments.peripheral_address.first().map(String::as_str),
+            arguments.peripheral_name.as_deref(),
+        )
+        .or_else(|| {
+            // Passive mode has no menu to fall back to.
+            let remember = !arguments.no_remember && !arguments.passive;
+            remember
+                .then(remember::load)
+                .flatten()
+                .map(PeripheralTarget::Remembered)
+        })
+        .unwrap_or(PeripheralTarget::Interactive),
+    };
+    let backup_target = peripheral_target(
+        arguments.backup_address.as_deref(),
+        arguments.backup_name.as_deref(),
+    );
+    let mut group_targets = vec![];
+    for address in arguments.peripheral_address.iter().skip(1) {
+        let Some(target) = peripheral_target(Some(address), None) else {
+            bail!("Invalid peripheral address {}", address);
+        };
+        group_targets.push(target);
+    }
 
-    loop {
-        match timeout(threshold, connected_peripheral.notification_stream.next()).await {
-            Ok(Some(data)) => {
-                info!(
-                    "Received data from {} [{:?}]: {:?}",
-                    connected_peripheral.name, data.uuid, data.value
-                );
-                let beats_per_minute: u8 = data.value[1];
-                let percent = f32::from(beats_per_minute) / f32::from(u8::MAX);
-                let message = OscPacket::Message(OscMessage {
-                    addr: String::from("/avatar/parameters/HeartRate"),
-                    args: vec![OscType::Float(percent)],
-                });
-                let buffer = encoder::encode(&message)?;
-                socket.send_to(&buffer, &arguments.receiver)?;
-                info!(
-                    "Sent message to host [{}]: {:?}",
-                    arguments.receiver, message
-                );
-
-                let now = Local::now().to_rfc3339();
-                let heart_rate = beats_per_minute.to_string();
-                writer.write_record(&[&now, &heart_rate])?;
-                writer.flush()?;
-            }
-            Ok(None) => {}
-            Err(_) => {
-                info!(
-                    "Timed out while waiting for a notification from {}",
-                    connected_peripheral.name
-                );
-                connected_peripheral =
-                    connect_to_peripheral(adapter, Some(connected_peripheral.address)).await?
+    let (event_sender, mut events) = mpsc::channel(32);
+    let primary = match (&arguments.reprocess, &adapter) {
+        (Some(path), _) => Device::reprocess(
+            PRIMARY_DEVICE,
+            capture::read_capture(path)?,
+            arguments.reprocess_fast,
+            event_sender.clone(),
+        ),
+        (None, _) if arguments.simulate => {
+            Device::simulate(PRIMARY_DEVICE, arguments.simulate_bpm, event_sender.clone())
+        }
+        (None, Some(adapter)) => Device::spawn(
+            PRIMARY_DEVICE,
+            adapter.clone(),
+            target,
+            primary_options(&device_options, backup_target.is_some(), &arguments),
+            event_sender.clone(),
+        ),
+        (None, None) => unreachable!("an adapter is selected unless reprocessing or simulating"),
+    };
+
+    if let Some(seconds) = arguments.record_and_exit {
+        let recording = recording::record(&mut events, Duration::from_secs(seconds)).await?;
+        if !arguments.no_log {
+            let path = log_path(&arguments, &log_name(), LogFormat::Csv);
+            let mut writer = get_log_writer(&arguments, &path)?;
+            for sample in &recording.samples {
+                writer.write_record(telemetry::csv_record(sample))?;
             }
+            writer.flush()?;
         }
+        print!("{}", RecordingStats::compute(&recording, primary.dropped()));
+        return Ok(());
     }
-}
 
-async fn interactive_peripheral_scan(adapter: &Adapter) -> Result<Peripheral> {
-    loop {
-        adapter.scan_for(1).await?;
+    let mut backup = None;
+    let mut primary_peripheral: Option<(BDAddr, Option<i16>)> = None;
+    let mut battery_level = None;
+    let rr_dependents = [(OscFeature::HeartBeat, "HeartRateBeat")]
+        .into_iter()
+        .filter(|(feature, _)| outputs.contains(feature))
+        .map(|(_, name)| name)
+        .collect();
+    // RMSSD computed from the heart rate wouldn't be HRV at all.
+    let rr_withheld = [(OscFeature::Hrv, "HeartRateHRV")]
+        .into_iter()
+        .filter(|(feature, _)| outputs.contains(feature))
+        .map(|(_, name)| name)
+        .collect();
+    let mut rr_availability =
+        RrAvailability::new(RR_WARMUP, rr_dependents, rr_withheld, Instant::now());
+    let mut failover = Failover::default();
+    let mut merger = Merger::new(arguments.merge_mode, threshold);
+    let mut contact_filter = arguments
+        .require_contact
+        .then(|| ContactFilter::new(Instant::now()));
 
-        let peripherals = adapter.peripherals().await?;
-        if peripherals.is_empty() {
-            info!("No peripherals found, scanning again");
+    let mut sinks: Vec<Box<dyn LogSink>> = vec![];
+    let log_name = log_name();
+    let log_formats = if arguments.no_log {
+        &[][..]
+    } else {
+        &arguments.log_format[..]
+    };
+    for (index, format) in log_formats.iter().enumerate() {
+        if log_formats[..index].contains(format) {
             continue;
         }
-
-        let mut peripheral_selection_items = vec![String::from("[Scan again]")];
-        let mut peripheral_local_names = get_peripheral_local_names(&peripherals)
-            .await
+        let path = log_path(&arguments, &log_name, *format);
+        if let Some(max_log_files) = arguments.max_log_files {
+            logfiles::clean_up(&path, format.extension(), max_log_files)?;
+        }
+        sinks.push(match format {
+            LogFormat::Csv => Box::new(CsvSink::new(get_log_writer(&arguments, &path)?)),
+            LogFormat::Jsonl => Box::new(JsonlSink::open(&path, arguments.log_append)?),
+        });
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &arguments.sqlite {
+        let session_id = Local::now().to_rfc3339();
+        sinks.push(Box::new(sqlite::SqliteSink::open(path, session_id)?));
+    }
+    #[cfg(unix)]
+    if let Some(path) = &arguments.fifo {
+        sinks.push(Box::new(fifo::FifoSink::create(path)?));
+    }
+    if arguments.json_stdout {
+        sinks.push(Box::new(StdoutSink::new()));
+    }
+    #[cfg(feature = "mqtt")]
+    if let (Some(url), Some(topic)) = (&arguments.mqtt_url, &arguments.mqtt_topic) {
+        sinks.push(Box::new(mqtt::MqttSink::connect(url, topic.clone())?));
+    }
+    let mut capture = arguments
+        .capture
+        .as_ref()
+        .map(CaptureWriter::create)
+        .transpose()?;
+    let sse = match arguments.sse_port {
+        Some(port) => Some(sse::spawn(port).await?),
+        None => None,
+    };
+    let status = match arguments.http_port {
+        Some(port) => Some(status::spawn(port).await?),
+        None => None,
+    };
+    let metrics = match arguments.metrics_port {
+        Some(port) => Some(metrics::spawn(port).await?),
+        None => None,
+    };
+    let mut session_state = SessionState::new(TelemetryOptions {
+        string_output,
+        ..telemetry_options(&arguments, preset, outputs.clone(), osc_prefix.clone())
+    })?;
+    let mut group = BTreeMap::new();
+    for index in 0..group_targets.len() {
+        // The combined and string messages have a single address, and only
+        // the beats and RR intervals of the first peripheral are tracked, so
+        // they and HeartRateBeat and HeartRateHRV stay with it.
+        let number = index + 2;
+        let outputs = outputs
             .iter()
-            .map(|local_name| match local_name {
-                Some(local_name) => local_name.clone(),
-                None => String::from("(Empty)"),
-            })
+            .copied()
+            .filter(|output| ![OscFeature::HeartBeat, OscFeature::Hrv].contains(output))
             .collect();
-        peripheral_selection_items.append(&mut peripheral_local_names);
-
-        let peripheral_selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select bluetooth peripheral")
-            .default(0)
-            .items(&peripheral_selection_items)
-            .interact()?;
-        if peripheral_selection == 0 {
-            info!("User chose to scan again");
-            continue;
+        let osc_prefix = format!("{}{}", arguments.osc_prefix, number);
+        let options = TelemetryOptions {
+            combined_message: None,
+            ..telemetry_options(&arguments, preset, outputs, osc_prefix)
+        };
+        group.insert(
+            FIRST_GROUP_DEVICE + index,
+            GroupMember::new(number, options)?,
+        );
+    }
+    // Each strap warns on its own when it doesn't report sensor contact.
+    let mut group_contact_filters: BTreeMap<_, _> = group
+        .keys()
+        .filter(|_| arguments.require_contact)
+        .map(|&device| (device, ContactFilter::new(Instant::now())))
+        .collect();
+    osc.clamp(session_state.unit_range_addresses());
+    for member in group.values() {
+        osc.clamp(member.unit_range_addresses());
+    }
+    // Without --age-max HeartRateAge is in seconds.
+    if arguments.age_max.is_some() && outputs.contains(&OscFeature::Age) {
+        osc.clamp([parameter("Age")]);
+    }
+    let mut group_devices = vec![];
+    let mut gauge_tick = time::interval(GAUGE_TICK_INTERVAL);
+    let mut awaiting_first_sample = false;
+    let mut primary_connected_before = false;
+
+    let mut log_paused = false;
+    let mut last_sample = None;
+    let mut session_stats = SessionStats::new(Instant::now());
+    let mut energy = EnergyAccumulator::new();
+    let mut energy_expended = None;
+    let mut last_notification = HashMap::new();
+    let mut timing_report = arguments.timing_report.then(TimingReport::new);
+    let age_max = arguments
+        .age_max
+        .and_then(|max| Duration::try_from_secs_f32(max).ok());
+    let mut age_tick = time::interval(Duration::from_millis(
+        arguments.age_interval_ms.unwrap_or(1000).max(1),
+    ));
+    let searching_window = arguments
+        .searching_window
+        .filter(|_| outputs.contains(&OscFeature::Searching))
+        .map(Duration::from_millis);
+    let mut searching_check = time::interval(SEARCHING_CHECK_INTERVAL);
+    let mut searching_sent = None;
+    let keepalive_interval = arguments.keepalive_interval.map(Duration::from_millis);
+    let mut keepalive_tick = time::interval(keepalive_interval.unwrap_or(Duration::from_secs(1)));
+
+    let mut stronger_check = time::interval(STRONGER_PERIPHERAL_CHECK_INTERVAL);
+    let mut stronger_tracker = StrongerPeripheralTracker::new(
+        arguments.prefer_stronger_margin,
+        Duration::from_secs(arguments.prefer_stronger_duration),
+    );
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let next_beat = session_state.next_beat();
+        tokio::select! {
+            Some(event) = events.recv() => match event {
+                DeviceEvent::Connected { device, address, rssi } => {
+                    if let Some(member) = group.get_mut(&device) {
+                        osc.send_packets(member.connected()).await?;
+                        continue;
+                    }
+                    if device != PRIMARY_DEVICE {
+                        continue;
+                    }
+                    battery_level = None;
+                    energy.restart();
+                    rr_availability.reset(Instant::now());
+                    primary_peripheral = Some((address, rssi));
+                    if !arguments.no_remember && adapter.is_some() {
+                        if let Err(error) = remember::save(address) {
+                            info!("Failed to remember {}: {}", address, error);
+                        }
+                    }
+                    stronger_tracker.reset();
+                    if let (true, Some(metrics)) = (primary_connected_before, &metrics) {
+                        metrics.send_modify(|metrics| metrics.reconnects += 1);
+                    }
+                    let packets = session_state.connected(primary_connected_before);
+                    osc.send_packets(packets).await?;
+                    primary_connected_before = true;
+                    awaiting_first_sample = true;
+                    if outputs.contains(&OscFeature::Connected) {
+                        osc.send(&parameter("Connected"), OscType::Bool(true)).await?;
+                    }
+                    session_stats.connected();
+                    if let Some(status) = &status {
+                        status.send_modify(|status| status.connected = true);
+                    }
+
+                    // The backup is only connected once the primary is, so
+                    // their scans don't interfere with each other.
+                    if let (None, Some(backup_target), Some(adapter)) =
+                        (&backup, &backup_target, &adapter)
+                    {
+                        backup = Some(Device::spawn(
+                            BACKUP_DEVICE,
+                            adapter.clone(),
+                            backup_target.clone(),
+                            device_options.clone(),
+                            event_sender.clone(),
+                        ));
+                    }
+                    if let (true, Some(adapter)) = (group_devices.is_empty(), &adapter) {
+                        for (index, target) in group_targets.iter().enumerate() {
+                            group_devices.push(Device::spawn(
+                                FIRST_GROUP_DEVICE + index,
+                                adapter.clone(),
+                                target.clone(),
+                                device_options.clone(),
+                                event_sender.clone(),
+                            ));
+                        }
+                    }
+                }
+                DeviceEvent::Disconnected { device } => {
+                    if let Some(member) = group.get_mut(&device) {
+                        osc.send_packets(member.disconnected()).await?;
+                        continue;
+                    }
+                    if device == PRIMARY_DEVICE {
+                        session_state.disconnected();
+                    }
+                    if let (PRIMARY_DEVICE, Some(status)) = (device, &status) {
+                        status.send_modify(|status| status.connected = false);
+                    }
+                    if device == PRIMARY_DEVICE && outputs.contains(&OscFeature::Connected) {
+                        osc.send(&parameter("Connected"), OscType::Bool(false)).await?;
+                    }
+                }
+                DeviceEvent::Battery { device, battery_level: level } => {
+                    if let Some(member) = group.get_mut(&device) {
+                        osc.send_packets(member.battery(level)).await?;
+                        continue;
+                    }
+                    if device == PRIMARY_DEVICE {
+                        battery_level = Some(level);
+                        session_stats.battery(level);
+                        if let Some(status) = &status {
+                            status.send_modify(|status| status.battery = Some(level));
+                        }
+                        if let Some(metrics) = &metrics {
+                            metrics.send_modify(|metrics| metrics.battery = Some(level));
+                        }
+                        osc.send_packets(session_state.battery(level)).await?;
+                    }
+                }
+                DeviceEvent::Information { device, model, firmware } => {
+                    if device == PRIMARY_DEVICE {
+                        session_stats.information(model, firmware);
+                    }
+                }
+                DeviceEvent::Notification { device, notification: data, captured_at } => {
+                    let parsed = match measurement::parse_notification(data.uuid, &data.value) {
+                        Notification::HeartRate(parsed) => parsed,
+                        Notification::Unknown(uuid) => {
+                            info!("Ignoring notification from characteristic {}", uuid);
+                            continue;
+                        }
+                    };
+                    if let Some(metrics) = &metrics {
+                        metrics.send_modify(|metrics| {
+                            metrics.notifications += 1;
+                            metrics.last_seen = Some(Local::now().timestamp());
+                        });
+                    }
+                    if let Some(member) = group.get_mut(&device) {
+                        let Some(parsed) = parsed else {
+                            info!("Ignoring malformed heart rate measurement {:?}", data.value);
+                            continue;
+                        };
+                        if let Some(contact_filter) = group_contact_filters.get_mut(&device) {
+                            if !contact_filter.accept(parsed.sensor_contact, Instant::now()) {
+                                continue;
+                            }
+                        }
+                        let energy_expended = member.energy_expended(&parsed);
+                        let sample = Sample {
+                            energy_expended,
+                            ..Sample::new(
+                                captured_at.unwrap_or_else(Local::now).to_rfc3339(),
+                                parsed.beats_per_minute(),
+                                &parsed,
+                                member.number,
+                            )
+                        };
+                        let plausible = check_plausible(&parsed, &valid_bpm);
+                        if plausible {
+                            osc.start_bundle();
+                            let packets = member.measurement(&parsed, energy_expended);
+                            osc.send_packets(packets).await?;
+                            osc.finish_bundle().await?;
+                        }
+                        if !log_paused && (plausible || !arguments.drop_invalid_bpm) {
+                            for sink in &mut sinks {
+                                sink.write(&sample, member.battery_level())?;
+                            }
+                        }
+                        if let (true, Some(sse)) = (plausible, &sse) {
+                            let _ = sse.send(sample);
+                        }
+                        continue;
+                    }
+                    if let Some(report) = &mut timing_report {
+                        let now = Instant::now();
+                        if let Some(last) = last_notification.insert(device, now) {
+                            report.notifications.record(now.duration_since(last));
+                        }
+                        let rr_intervals = parsed.iter().flat_map(|parsed| &parsed.rr_intervals);
+                        for rr_interval in rr_intervals {
+                            let seconds = f64::from(*rr_interval) / 1024.0;
+                            report.rr_intervals.record(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                    let role = if device == BACKUP_DEVICE {
+                        Role::Backup
+                    } else {
+                        Role::Primary
+                    };
+                    let merging = arguments.merge_mode != MergeMode::Separate;
+                    if !merging && !failover.accept(role) {
+                        continue;
+                    }
+
+                    if let Some(capture) = &mut capture {
+                        capture.write(&data)?;
+                    }
+                    let Some(parsed) = parsed else {
+                        info!("Ignoring malformed heart rate measurement {:?}", data.value);
+                        continue;
+                    };
+                    // The backup is logged after the only --peripheral-address.
+                    let number = if device == BACKUP_DEVICE { 2 } else { 1 };
+                    if let Some(contact_filter) = &mut contact_filter {
+                        if !contact_filter.accept(parsed.sensor_contact, Instant::now()) {
+                            continue;
+                        }
+                    }
+                    // Only the primary's counter is accumulated, and reset.
+                    let mut sample_energy = None;
+                    if let (PRIMARY_DEVICE, Some(raw_energy)) = (device, parsed.energy_expended) {
+                        sample_energy = Some(energy.update(raw_energy));
+                        energy_expended = sample_energy;
+                        let near_overflow = raw_energy >= energy::NEAR_OVERFLOW;
+                        let reset = arguments.reset_energy_near_overflow && near_overflow;
+                        if reset && energy.can_request_reset() {
+                            energy.expect_reset();
+                            primary.reset_energy_expended().await;
+                        }
+                    }
+                    if !check_plausible(&parsed, &valid_bpm) {
+                        if !log_paused && !arguments.drop_invalid_bpm {
+                            let timestamp = captured_at.unwrap_or_else(Local::now).to_rfc3339();
+                            let sample = Sample {
+                                energy_expended: sample_energy,
+                                ..Sample::new(timestamp, parsed.beats_per_minute(), &parsed, number)
+                            };
+                            for sink in &mut sinks {
+                                sink.write(&sample, battery_level)?;
+                            }
+                        }
+                        continue;
+                    }
+                    // Merged readings are sent once, with the RR intervals of
+                    // the peripheral driving the output.
+                    let merged = merger.update(device, parsed.beats_per_minute(), Instant::now());
+                    let Some(beats_per_minute) = merged else {
+                        continue;
+                    };
+                    rr_availability.observe(!parsed.rr_intervals.is_empty(), Instant::now());
+                    session_state.set_rr_available(rr_availability.available());
+                    osc.start_bundle();
+                    if device == PRIMARY_DEVICE && awaiting_first_sample {
+                        awaiting_first_sample = false;
+                        session_state.started();
+                    }
+                    let packets = telemetry::process_measurement(
+                        &parsed,
+                        beats_per_minute,
+                        sample_energy,
+                        battery_level,
+                        &mut session_state,
+                    );
+                    osc.send_packets(packets).await?;
+                    osc.finish_bundle().await?;
+
+                    let sample = Sample {
+                        energy_expended: sample_energy,
+                        hrv: session_state.hrv(),
+                        ..Sample::new(
+                            captured_at.unwrap_or_else(Local::now).to_rfc3339(),
+                            beats_per_minute,
+                            &parsed,
+                            number,
+                        )
+                    };
+                    last_sample = Some(Instant::now());
+                    session_stats.sample(beats_per_minute);
+                    if !log_paused {
+                        for sink in &mut sinks {
+                            sink.write(&sample, battery_level)?;
+                        }
+                    }
+
+                    if let Some(metrics) = &metrics {
+                        metrics.send_modify(|metrics| {
+                            metrics.heart_rate = Some(sample.heart_rate);
+                            metrics.osc_send_errors = osc.dropped();
+                        });
+                    }
+                    if let Some(status) = &status {
+                        status.send_modify(|status| {
+                            status.bpm = Some(sample.heart_rate);
+                            status.updated_at = Some(sample.timestamp.clone());
+                        });
+                    }
+
+                    if let Some(sse) = &sse {
+                        // Sending only fails when no client is listening.
+                        let _ = sse.send(sample);
+                    }
+                }
+                DeviceEvent::EnergyResetFailed { device } => {
+                    if device == PRIMARY_DEVICE {
+                        energy.reset_failed();
+                    }
+                }
+                DeviceEvent::ReconnectLimitReached { device } => {
+                    if device == PRIMARY_DEVICE {
+                        failover.primary_failed();
+                    }
+                }
+                DeviceEvent::Failed { device, error } => {
+                    // The session only ends with the primary, the others
+                    // stop streaming on their own.
+                    if device != PRIMARY_DEVICE {
+                        group_contact_filters.remove(&device);
+                        if let Some(mut member) = group.remove(&device) {
+                            warn!("Dropping peripheral {}: {:#}", member.number, error);
+                            osc.send_packets(member.disconnected()).await?;
+                        } else {
+                            warn!("Dropping the backup peripheral: {:#}", error);
+                        }
+                        continue;
+                    }
+                    for sink in &mut sinks {
+                        // Keep whatever was logged, the device error is what matters.
+                        let _ = sink.flush();
+                    }
+                    if let Some(path) = &arguments.run_summary {
+                        let summary = session_stats.summary(primary.dropped(), Instant::now());
+                        let _ = summary.write(path);
+                    }
+                    return Err(error);
+                }
+                DeviceEvent::Finished { device } => {
+                    if device == PRIMARY_DEVICE {
+                        info!("Finished reprocessing");
+                        break;
+                    }
+                }
+            },
+            _ = gauge_tick.tick(), if session_state.eases() => {
+                let now = Instant::now();
+                osc.send_packets(session_state.gauge_tick(now)).await?;
+                for member in group.values_mut() {
+                    osc.send_packets(member.gauge_tick(now)).await?;
+                }
+            }
+            _ = age_tick.tick(), if outputs.contains(&OscFeature::Age) => {
+                if let Some(age) = age::data_age(last_sample, Instant::now(), age_max) {
+                    osc.send(&parameter("Age"), OscType::Float(age)).await?;
+                }
+            }
+            _ = time::sleep_until(next_beat.unwrap_or_else(Instant::now).into()),
+                if next_beat.is_some() =>
+            {
+                let beat = session_state.beat();
+                osc.send_packets(vec![beat]).await?;
+            }
+            _ = keepalive_tick.tick(), if keepalive_interval.is_some() => {
+                let Some(interval) = keepalive_interval else {
+                    continue;
+                };
+                let now = Instant::now();
+                if age::needs_keepalive(last_sample, now, interval, threshold) {
+                    let packets = session_state.keepalive(battery_level, now);
+                    osc.start_bundle();
+                    osc.send_packets(packets).await?;
+                    osc.finish_bundle().await?;
+                }
+                for member in group.values() {
+                    let packets = member.keepalive(now, interval, threshold);
+                    if !packets.is_empty() {
+                        osc.start_bundle();
+                        osc.send_packets(packets).await?;
+                        osc.finish_bundle().await?;
+                    }
+                }
+            }
+            _ = searching_check.tick(), if searching_window.is_some() => {
+                let Some(window) = searching_window else {
+                    continue;
+                };
+                let searching = age::is_searching(last_sample, Instant::now(), window);
+                if searching_sent != Some(searching) {
+                    osc.send(&parameter("Searching"), OscType::Bool(searching)).await?;
+                    searching_sent = Some(searching);
+                }
+            }
+            _ = stronger_check.tick(), if arguments.prefer_stronger => {
+                let (Some(name), Some((address, rssi)), Some(adapter)) =
+                    (&arguments.peripheral_name, &mut primary_peripheral, &adapter)
+                else {
+                    continue;
+                };
+                let mut candidates = adapter.matching_peripheral_rssis(name).await?;
+                if let Some(index) = candidates
+                    .iter()
+                    .position(|(candidate, _)| candidate == address)
+                {
+                    *rssi = Some(candidates.swap_remove(index).1);
+                }
+                let Some(current_rssi) = *rssi else {
+                    continue;
+                };
+                if let Some(stronger_address) =
+                    stronger_tracker.update(current_rssi, &candidates, Instant::now())
+                {
+                    info!("Found stronger peripheral {}", stronger_address);
+                    primary.switch_to(stronger_address).await;
+                }
+            }
+            Some(message) = async { inbound.as_mut()?.messages.recv().await },
+                if inbound.is_some() =>
+            {
+                if arguments.pause_log_address.as_ref() == Some(&message.addr) {
+                    let paused = sink::requested_pause(&message.args, log_paused);
+                    if paused && !log_paused {
+                        for sink in &mut sinks {
+                            sink.flush()?;
+                        }
+                        info!("Paused logging");
+                    } else if !paused && log_paused {
+                        info!("Resumed logging");
+                    }
+                    log_paused = paused;
+                }
+                let avatar_changed = message.addr == inbound::AVATAR_CHANGE_ADDRESS;
+                if !avatar_changed || arguments.osc_listen.is_none() {
+                    continue;
+                }
+                info!("Avatar changed, sending the parameters again");
+                let now = Instant::now();
+                osc.start_bundle();
+                osc.send_packets(session_state.avatar_changed(battery_level, now))
+                    .await?;
+                for member in group.values() {
+                    osc.send_packets(member.avatar_changed(now)).await?;
+                }
+                osc.finish_bundle().await?;
+            }
+            result = &mut shutdown => {
+                result?;
+                info!("Shutting down");
+                break;
+            }
         }
+    }
 
-        // Account for the "scan again" item.
-        let peripheral_index = peripheral_selection - 1;
+    primary.disconnect().await;
+    if let Some(backup) = &backup {
+        backup.disconnect().await;
+    }
+    if outputs.contains(&OscFeature::Connected) && primary_connected_before {
+        osc.send(&parameter("Connected"), OscType::Bool(false))
+            .await?;
+    }
+    for sink in &mut sinks {
+        sink.flush()?;
+    }
+    if let Some(energy_expended) = energy_expended {
+        info!("Energy expended this session: {} kJ", energy_expended);
+    }
+    match &timing_report {
+        // Stdout only has samples then.
+        Some(report) if arguments.json_stdout => eprint!("{}", report),
+        Some(report) => print!("{}", report),
+        None => {}
+    }
+    if let Some(path) = &arguments.run_summary {
+        session_stats
+            .summary(primary.dropped(), Instant::now())
+            .write(path)?;
+        info!("Wrote session summary to {}", path);
+    }
+    Ok(())
+}
 
-        match peripherals.get(peripheral_index).cloned() {
-            Some(peripheral) => break Ok(peripheral),
-            None => continue,
+/// Resolves on Ctrl-C, or on SIGTERM on Unix.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
         }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// Gets the Bluetooth adapters, retrying while the Bluetooth stack isn't
+/// available yet, like right after boot or toggling the adapter.
+async fn adapters(retries: u32) -> Result<Vec<Adapter>> {
+    let mut backoff = Backoff::retrying(retries);
+    loop {
+        let adapters = match Manager::new().await {
+            Ok(manager) => manager.adapters().await,
+            Err(error) => Err(error),
+        };
+        let error = match adapters {
+            Ok(adapters) if !adapters.is_empty() => return Ok(adapters),
+            Ok(_) => anyhow!("Found no Bluetooth adapters, is Bluetooth turned on?"),
+            Err(error) => anyhow!("Failed to get the Bluetooth adapters: {}", error),
+        };
+        let Some(delay) = backoff.next_delay() else {
+            return Err(error);
+        };
+        info!(
+            "{}, retrying in {:?} ({} of {})",
+            error,
+            delay,
+            backoff.attempts(),
+            retries
+        );
+        time::sleep(delay).await;
+    }
+}
+
+/// Binds the sender socket, falling back to a port picked by the OS when the
+/// default one is taken and --sender wasn't given, as the source port of the
+/// messages rarely matters.
+fn bind_sender(sender: Option<SocketAddr>, receivers: &[SocketAddr]) -> Result<UdpSocket> {
+    let ipv6 = receivers.first().is_some_and(SocketAddr::is_ipv6);
+    let address = sender.unwrap_or_else(|| match ipv6 {
+        true => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_SENDER_PORT),
+        false => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_SENDER_PORT),
+    });
+    // A socket can only send to its own address family.
+    if let Some(receiver) = receivers
+        .iter()
+        .find(|receiver| receiver.is_ipv6() != address.is_ipv6())
+    {
+        bail!(
+            "The receiver {} and the sender address {} aren't both IPv4 or IPv6",
+            receiver,
+            address
+        );
+    }
+    let socket = match UdpSocket::bind(address) {
+        Ok(socket) => socket,
+        Err(error) if error.kind() == io::ErrorKind::AddrInUse && sender.is_none() => {
+            warn!(
+                "The sender address {} is in use, using any free port",
+                address
+            );
+            let unspecified = match ipv6 {
+                true => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+                false => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            };
+            UdpSocket::bind(unspecified).context("Failed to bind to a free sender port")?
+        }
+        Err(error) if error.kind() == io::ErrorKind::AddrInUse => bail!(
+            "The sender address {} is in use, likely by another instance or OSC app",
+            address
+        ),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to bind to the sender address {}", address))
+        }
+    };
+    info!("Binded to address {}", socket.local_addr()?);
+    Ok(socket)
 }
 
-async fn get_peripheral_local_names(peripherals: &[Peripheral]) -> Vec<Option<String>> {
-    join_all(
-        peripherals
+async fn select_adapter(selector: Option<&str>, retries: u32) -> Result<Adapter> {
+    let adapters = adapters(retries).await?;
+    if adapters.len() == 1 && selector.is_none() {
+        return Ok(adapters.into_iter().next().unwrap());
+    }
+    let adpater_selection_items = join_all(
+        adapters
             .iter()
-            .map(|peripheral| async {
-                match peripheral.properties().await {
-                    Err(_) => None,
-                    Ok(properties) => {
-                        if let Some(properties) = properties {
-                            properties.local_name
-                        } else {
-                            None
-                        }
-                    }
+            .map(|adapter| async {
+                match adapter.adapter_info().await {
+                    Ok(info) => format!("{:?}", info),
+                    Err(error) => format!("(Unknown adapter: {})", error),
                 }
             })
             .collect::<Vec<_>>(),
     )
-    .await
+    .await;
+    let index = match selector {
+        Some(selector) => peripheral::match_adapter(&adpater_selection_items, selector)?,
+        None => {
+            let adapter_selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select bluetooth adapter")
+                .default(0)
+                .items(&adpater_selection_items)
+                .interact_opt();
+            prompt::selection(adapter_selection)?
+        }
+    };
+    Ok(adapters.into_iter().nth(index).unwrap())
 }
 
-struct ConnectedPeripheral {
-    address: BDAddr,
-    name: String,
-    notification_stream: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+fn resolve_outputs(arguments: &Arguments) -> Result<Vec<OscFeature>> {
+    let calm = arguments.calm_bpm.is_some();
+    let effort = arguments.resting_hr.is_some() && arguments.max_hr.is_some();
+    let spike = arguments.spike_delta.is_some();
+    let delta = arguments.baseline_delta.is_some() && arguments.resting_hr.is_some();
+    let age = arguments.age_interval_ms.is_some();
+    let searching = arguments.searching_window.is_some();
+    let hrv = arguments.hrv_window.is_some();
+    let smoothed = arguments.smoothing.is_some_and(|alpha| alpha > 0.0);
+    let calories =
+        arguments.age.is_some() && arguments.weight_kg.is_some() && arguments.sex.is_some();
+    let configurable: Vec<_> = [
+        (OscFeature::Bpm, true),
+        (OscFeature::Calm, calm),
+        (OscFeature::Effort, effort),
+        (OscFeature::Spike, spike),
+        (OscFeature::Age, age),
+        (OscFeature::Debug, true),
+        (OscFeature::Calories, calories),
+        (OscFeature::Reconnected, true),
+        (OscFeature::Started, true),
+        (OscFeature::Connected, true),
+        (OscFeature::Battery, !arguments.skip_battery),
+        (OscFeature::Delta, delta),
+        (OscFeature::Searching, searching),
+        (OscFeature::Smoothed, smoothed),
+        (OscFeature::Zone, true),
+        (OscFeature::HeartBeat, true),
+        (OscFeature::Energy, true),
+        (OscFeature::Hrv, hrv),
+    ]
+    .into_iter()
+    .filter_map(|(feature, configurable)| configurable.then_some(feature))
+    .collect();
+    let enabled: Vec<_> = [
+        (OscFeature::Bpm, true),
+        (OscFeature::Calm, calm),
+        (OscFeature::Effort, arguments.effort_index),
+        (OscFeature::Spike, spike),
+        (OscFeature::Age, age),
+        (OscFeature::Debug, arguments.debug_osc),
+        (OscFeature::Calories, arguments.calories),
+        (OscFeature::Reconnected, arguments.reconnect_cue),
+        (OscFeature::Started, arguments.started_cue),
+        (OscFeature::Connected, true),
+        (OscFeature::Battery, !arguments.skip_battery),
+        (OscFeature::Delta, delta),
+        (OscFeature::Searching, searching),
+        (OscFeature::Smoothed, smoothed),
+        (OscFeature::Zone, arguments.zone_max.is_some()),
+        (OscFeature::HeartBeat, arguments.heart_beat),
+        (OscFeature::Energy, true),
+        (OscFeature::Hrv, hrv),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect();
+    features::resolve(arguments.features.as_deref(), &enabled, &configurable)
 }
 
-async fn connect_to_peripheral(
-    adapter: &Adapter,
-    maybe_peripheral_address: Option<BDAddr>,
-) -> Result<ConnectedPeripheral> {
-    let peripheral = if let Some(peripheral_address) = maybe_peripheral_address {
-        adapter.scan_for_peripheral(peripheral_address).await?
-    } else {
-        interactive_peripheral_scan(adapter).await?
-    };
+/// The options of the primary peripheral, which only gives up after
+/// --backup-after reconnection attempts when there is a backup.
+fn primary_options(options: &DeviceOptions, backup: bool, arguments: &Arguments) -> DeviceOptions {
+    if !backup {
+        return options.clone();
+    }
+    let mut options = options.clone();
+    // Every attempt is a single bounded scan and connection, so a peripheral
+    // that is gone counts as failing rather than being scanned for forever.
+    options.connect.max_reconnect_attempts = Some(1);
+    options.connect.scan_timeout = options.connect.scan_timeout.or(Some(options.threshold));
+    options.failover_after = Some(arguments.backup_after);
+    options
+}
 
-    let peripheral_properties = peripheral.properties().await?.unwrap();
-    let peripheral_address = peripheral_properties.address;
-    let peripheral_local_name = peripheral_properties
-        .local_name
-        .unwrap_or(String::from("(Empty)"));
+/// Picks a target from an address, if it parses, or a name.
+fn peripheral_target(address: Option<&str>, name: Option<&str>) -> Option<PeripheralTarget> {
+    let address = address.and_then(|address| {
+        let delimiter = BDAddr::from_str_delim(address);
+        let no_delimiter = BDAddr::from_str_no_delim(address);
+        delimiter.or(no_delimiter).ok()
+    });
+    match (address, name) {
+        (Some(address), _) => Some(PeripheralTarget::Address(address)),
+        (None, Some(name)) => Some(PeripheralTarget::Name(String::from(name))),
+        (None, None) => None,
+    }
+}
 
-    info!(
-        "Connecting to {} [{}]",
-        peripheral_local_name, peripheral_address
-    );
-    while peripheral.connect().await.is_err() {
-        info!("Failed to connect to {}", peripheral_local_name)
+/// Warns about a measurement that can't be a real heart rate, returning
+/// whether it can be.
+fn check_plausible(measurement: &HeartRateMeasurement, valid: &RangeInclusive<u16>) -> bool {
+    if measurement.is_plausible(valid) {
+        return true;
     }
-    info!(
-        "Connected to {} [{}]",
-        peripheral_local_name, peripheral_address
-    );
+    match measurement.sensor_contact {
+        Some(false) => warn!(
+            "Ignoring {} bpm measured without sensor contact",
+            measurement.heart_rate
+        ),
+        _ => warn!(
+            "Ignoring implausible heart rate of {} bpm",
+            measurement.heart_rate
+        ),
+    }
+    false
+}
 
-    peripheral.discover_services().await?;
-    let characteristics = peripheral.characteristics();
+/// What the parameters under `osc_prefix` are computed from, besides the
+/// string output.
+fn telemetry_options(
+    arguments: &Arguments,
+    preset: &Preset,
+    outputs: Vec<OscFeature>,
+    osc_prefix: String,
+) -> TelemetryOptions {
+    TelemetryOptions {
+        outputs,
+        heart_rate_parameters: HeartRateParameters::new(
+            preset,
+            arguments.address_prefix.as_deref(),
+            &osc_prefix,
+        ),
+        osc_prefix,
+        min_heart_rate: arguments.min_heart_rate,
+        max_heart_rate: arguments.max_heart_rate,
+        zone_max: arguments.zone_max.unwrap_or(arguments.max_heart_rate),
+        init_value: arguments.init_value,
+        reset_smoothing_on_reconnect: arguments.reset_smoothing_on_reconnect,
+        combined_message: arguments.combined_message.clone(),
+        string_output: None,
+        calm_bpm: arguments.calm_bpm,
+        calm_duration: Duration::from_secs(arguments.calm_duration),
+        spike_delta: arguments.spike_delta,
+        calories: arguments
+            .age
+            .zip(arguments.weight_kg)
+            .zip(arguments.sex)
+            .map(|((age, weight_kg), sex)| (age, weight_kg, sex)),
+        effort: arguments.resting_hr.zip(arguments.max_hr),
+        retention: arguments.retention,
+        baseline_delta: arguments.resting_hr.zip(arguments.baseline_delta),
+        gauge_ease: arguments.gauge_ease_ms.map(Duration::from_millis),
+        smoothing: arguments.smoothing,
+        auto_seed_range: arguments.auto_seed_range.map(Duration::from_secs),
+        percentile_window: arguments
+            .percentile_range
+            .then_some(arguments.percentile_window),
+        hrv_window: arguments.hrv_window.map(Duration::from_secs),
+        hrv_max: f32::from(arguments.hrv_max),
+    }
+}
 
-    let battery_level_characteristic = characteristics
-        .iter()
-        .find(|characteristic| characteristic.uuid == BATTERY_LEVEL_CHARACTERISTIC_UUID)
-        .expect("Failed to get battery level characteristic");
-    let battery_level = peripheral.read(battery_level_characteristic).await?[0];
-    info!(
-        "Battery level of {}: {}",
-        peripheral_local_name, battery_level
-    );
+/// The name of the session logs, without an extension.
+fn log_name() -> String {
+    Local::now().format("%Y%m%d-%H%M%S").to_string()
+}
 
-    let heart_rate_characteristic = characteristics
-        .iter()
-        .find(|characteristic| characteristic.uuid == HEART_RATE_CHARACTERISTIC_UUID)
-        .expect("Failed to get heart rate characteristic");
-    peripheral.subscribe(heart_rate_characteristic).await?;
-    info!(
-        "Subscribed to heart rate characteristic of {}",
-        peripheral_local_name
-    );
+/// Where to log in the given format, --log-file or a file named after the session.
+fn log_path(arguments: &Arguments, log_name: &str, format: LogFormat) -> String {
+    match &arguments.log_file {
+        Some(path) if format == LogFormat::Csv => path.clone(),
+        Some(path) => Path::new(path)
+            .with_extension(format.extension())
+            .to_string_lossy()
+            .into_owned(),
+        None => format!("{}.{}", log_name, format.extension()),
+    }
+}
 
-    return Ok(ConnectedPeripheral {
-        address: peripheral_address,
-        name: peripheral_local_name,
-        notification_stream: peripheral.notifications().await?,
-    });
+/// Opens a CSV log, writing the header unless appending to a file that has one.
+fn get_log_writer(arguments: &Arguments, path: &str) -> Result<Writer<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(arguments.log_append)
+        .truncate(!arguments.log_append)
+        .open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut writer = sink::csv_writer(
+        file,
+        arguments.csv_delimiter as u8,
+        arguments.csv_quoting.into(),
+    );
+    if is_empty {
+        writer.write_record(sink::CSV_HEADER)?;
+        writer.flush()?;
+    }
+    Ok(writer)
 }
 
-fn get_log_writer() -> Result<Writer<File>> {
-    let time = Local::now().format("%Y%m%d-%H%M%S");
-    let log_name = format!("{}.csv", time);
-    return Writer::from_path(log_name).map_err(|error| error.into());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::{fs, process};
+
+    #[test]
+    fn dumped_configurations_load_back() {
+        let arguments = Arguments::parse_from([
+            "vrc_sa_node",
+            "--receiver",
+            "127.0.0.1:9000",
+            "--receiver",
+            "127.0.0.1:9002",
+            "--calm-bpm",
+            "60",
+            "--no-bundle",
+            "--csv-quoting",
+            "always",
+            "--features",
+            "bpm,zone",
+            "-vv",
+        ]);
+        let dumped = toml::to_string(&arguments).unwrap();
+        let path = env::temp_dir().join(format!("vrc_sa_node-config-{}.toml", process::id()));
+        fs::write(&path, &dumped).unwrap();
+
+        let matches = Arguments::command().get_matches_from(["vrc_sa_node"]);
+        let file_arguments = config::file_arguments(&Arguments::command(), &matches, &path);
+        fs::remove_file(&path).unwrap();
+        let program = std::iter::once(OsString::from("vrc_sa_node"));
+        let reloaded = Arguments::parse_from(program.chain(file_arguments.unwrap()));
+        assert_eq!(toml::to_string(&reloaded).unwrap(), dumped);
+        assert_eq!(reloaded.receiver, ["127.0.0.1:9000", "127.0.0.1:9002"]);
+        assert_eq!(reloaded.verbose, 2);
+    }
+
+    #[test]
+    fn features_parse_as_a_list() {
+        let arguments = Arguments::parse_from(["vrc_sa_node", "--features", "bpm,battery,zone"]);
+        let features = [OscFeature::Bpm, OscFeature::Battery, OscFeature::Zone];
+        assert_eq!(arguments.features.as_deref(), Some(&features[..]));
+
+        let error = Arguments::try_parse_from(["vrc_sa_node", "--features", "bpm,trend"])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("invalid value 'trend'"));
+        assert!(error.contains("possible values: bpm, calm,"));
+    }
 }
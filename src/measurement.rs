@@ -0,0 +1,195 @@
+use crate::peripheral::HEART_RATE_CHARACTERISTIC_UUID;
+use std::ops::RangeInclusive;
+use uuid::Uuid;
+
+const HEART_RATE_UINT16: u8 = 0x01;
+const SENSOR_CONTACT_DETECTED: u8 = 0x02;
+const SENSOR_CONTACT_SUPPORTED: u8 = 0x04;
+const ENERGY_EXPENDED_PRESENT: u8 = 0x08;
+const RR_INTERVALS_PRESENT: u8 = 0x10;
+
+/// A parsed Heart Rate Measurement (0x2A37) value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeartRateMeasurement {
+    pub flags: u8,
+    pub heart_rate: u16,
+    pub sensor_contact: Option<bool>,
+    /// Kilojoules since the last reset.
+    pub energy_expended: Option<u16>,
+    /// In units of 1/1024 seconds.
+    pub rr_intervals: Vec<u16>,
+}
+
+impl HeartRateMeasurement {
+    /// The RR intervals rounded to milliseconds.
+    pub fn rr_intervals_ms(&self) -> Vec<u16> {
+        self.rr_intervals
+            .iter()
+            .map(|rr_interval| (u32::from(*rr_interval) * 1000 + 512) / 1024)
+            .map(|milliseconds| milliseconds as u16)
+            .collect()
+    }
+
+    /// The heart rate, saturated to what fits the rest of the pipeline.
+    pub fn beats_per_minute(&self) -> u8 {
+        u8::try_from(self.heart_rate).unwrap_or(u8::MAX)
+    }
+
+    /// Whether the heart rate is within `valid` and wasn't taken without
+    /// sensor contact, unlike the 0 bpm some straps send while warming up.
+    pub fn is_plausible(&self, valid: &RangeInclusive<u16>) -> bool {
+        self.sensor_contact != Some(false) && valid.contains(&self.heart_rate)
+    }
+
+    /// A compact encoding of every field, for debugging consumers:
+    /// `bpm=<n>;flags=<hex>;contact=<0|1|->;energy=<n|->;rr=<n,n,...>`.
+    pub fn debug_string(&self) -> String {
+        let contact = match self.sensor_contact {
+            Some(true) => String::from("1"),
+            Some(false) => String::from("0"),
+            None => String::from("-"),
+        };
+        let energy = match self.energy_expended {
+            Some(energy) => energy.to_string(),
+            None => String::from("-"),
+        };
+        let rr_intervals: Vec<_> = self.rr_intervals.iter().map(u16::to_string).collect();
+        format!(
+            "bpm={};flags={:02x};contact={};energy={};rr={}",
+            self.heart_rate,
+            self.flags,
+            contact,
+            energy,
+            rr_intervals.join(",")
+        )
+    }
+}
+
+/// A notification, told apart by the characteristic it came from.
+#[derive(Debug, PartialEq)]
+pub enum Notification {
+    /// `None` when the measurement is malformed.
+    HeartRate(Option<HeartRateMeasurement>),
+    Unknown(Uuid),
+}
+
+/// Parses a notification according to its characteristic, so nothing else
+/// is ever taken for a heart rate measurement.
+pub fn parse_notification(uuid: Uuid, value: &[u8]) -> Notification {
+    match uuid {
+        HEART_RATE_CHARACTERISTIC_UUID => {
+            Notification::HeartRate(parse_heart_rate_measurement(value))
+        }
+        uuid => Notification::Unknown(uuid),
+    }
+}
+
+pub fn parse_heart_rate_measurement(value: &[u8]) -> Option<HeartRateMeasurement> {
+    let (&flags, rest) = value.split_first()?;
+    let (heart_rate, mut rest) = if flags & HEART_RATE_UINT16 != 0 {
+        (read_u16(rest)?, rest.get(2..)?)
+    } else {
+        (u16::from(*rest.first()?), &rest[1..])
+    };
+
+    let energy_expended = if flags & ENERGY_EXPENDED_PRESENT != 0 {
+        let energy_expended = read_u16(rest)?;
+        rest = &rest[2..];
+        Some(energy_expended)
+    } else {
+        None
+    };
+
+    let rr_intervals = if flags & RR_INTERVALS_PRESENT != 0 {
+        rest.chunks_exact(2).filter_map(read_u16).collect()
+    } else {
+        vec![]
+    };
+
+    Some(HeartRateMeasurement {
+        flags,
+        heart_rate,
+        sensor_contact: sensor_contact(value),
+        energy_expended,
+        rr_intervals,
+    })
+}
+
+fn read_u16(bytes: &[u8]) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]))
+}
+
+/// Returns whether the sensor has contact, if it reports it at all.
+pub fn sensor_contact(value: &[u8]) -> Option<bool> {
+    let flags = value.first()?;
+    (flags & SENSOR_CONTACT_SUPPORTED != 0).then_some(flags & SENSOR_CONTACT_DETECTED != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::BATTERY_LEVEL_CHARACTERISTIC_UUID;
+
+    #[test]
+    fn parses_8_bit_heart_rate() {
+        let measurement = parse_heart_rate_measurement(&[0x16, 72, 0x00, 0x04]).unwrap();
+        assert_eq!(measurement.heart_rate, 72);
+        assert_eq!(measurement.beats_per_minute(), 72);
+        assert_eq!(measurement.sensor_contact, Some(true));
+        assert_eq!(measurement.energy_expended, None);
+        assert_eq!(measurement.rr_intervals, vec![1024]);
+        assert_eq!(measurement.rr_intervals_ms(), vec![1000]);
+    }
+
+    #[test]
+    fn parses_16_bit_heart_rate() {
+        let value = [0x09, 0x2c, 0x01, 0x10, 0x00];
+        let measurement = parse_heart_rate_measurement(&value).unwrap();
+        assert_eq!(measurement.heart_rate, 300);
+        assert_eq!(measurement.beats_per_minute(), u8::MAX);
+        assert_eq!(measurement.sensor_contact, None);
+        assert_eq!(measurement.energy_expended, Some(16));
+        assert!(measurement.rr_intervals.is_empty());
+    }
+
+    #[test]
+    fn rejects_too_short_values() {
+        assert_eq!(parse_heart_rate_measurement(&[]), None);
+        assert_eq!(parse_heart_rate_measurement(&[0x00]), None);
+        assert_eq!(parse_heart_rate_measurement(&[0x01, 0x2c]), None);
+        assert_eq!(parse_heart_rate_measurement(&[0x08, 72, 0x10]), None);
+    }
+
+    #[test]
+    fn debug_string_lists_every_field() {
+        let measurement =
+            parse_heart_rate_measurement(&[0x1f, 0x96, 0x00, 0x2c, 0x01, 0x00, 0x04, 0x00, 0x02])
+                .unwrap();
+        assert_eq!(
+            measurement.debug_string(),
+            "bpm=150;flags=1f;contact=1;energy=300;rr=1024,512"
+        );
+        let measurement = parse_heart_rate_measurement(&[0x00, 72]).unwrap();
+        assert_eq!(
+            measurement.debug_string(),
+            "bpm=72;flags=00;contact=-;energy=-;rr="
+        );
+    }
+
+    #[test]
+    fn only_heart_rate_notifications_are_parsed() {
+        let value = [0x00, 72];
+        let parsed = parse_notification(HEART_RATE_CHARACTERISTIC_UUID, &value);
+        assert_eq!(
+            parsed,
+            Notification::HeartRate(parse_heart_rate_measurement(&value))
+        );
+        let battery = BATTERY_LEVEL_CHARACTERISTIC_UUID;
+        assert_eq!(
+            parse_notification(battery, &value),
+            Notification::Unknown(battery)
+        );
+        let malformed = parse_notification(HEART_RATE_CHARACTERISTIC_UUID, &[0x01, 72]);
+        assert_eq!(malformed, Notification::HeartRate(None));
+    }
+}
@@ -0,0 +1,113 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(ValueEnum, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeMode {
+    Separate,
+    Average,
+    Max,
+}
+
+/// Combines the latest fresh reading of every peripheral into one heart rate.
+pub struct Merger {
+    mode: MergeMode,
+    staleness: Duration,
+    readings: BTreeMap<usize, (u8, Instant)>,
+}
+
+impl Merger {
+    pub fn new(mode: MergeMode, staleness: Duration) -> Self {
+        Self {
+            mode,
+            staleness,
+            readings: BTreeMap::new(),
+        }
+    }
+
+    /// Records a reading and returns the merged heart rate when `device`
+    /// drives the output, being the first one with a fresh reading, so every
+    /// round of readings is sent once.
+    pub fn update(&mut self, device: usize, heart_rate: u8, now: Instant) -> Option<u8> {
+        self.readings.insert(device, (heart_rate, now));
+        if self.mode == MergeMode::Separate {
+            return Some(heart_rate);
+        }
+        let fresh: Vec<_> = self
+            .readings
+            .iter()
+            .filter(|(_, (_, received_at))| now.duration_since(*received_at) < self.staleness)
+            .map(|(device, (heart_rate, _))| (*device, *heart_rate))
+            .collect();
+        // The reading just inserted is always fresh, so there is a first one.
+        if fresh.first().map(|(first, _)| *first) != Some(device) {
+            return None;
+        }
+
+        let heart_rates = fresh.iter().map(|(_, heart_rate)| u32::from(*heart_rate));
+        let merged = match self.mode {
+            MergeMode::Separate | MergeMode::Max => heart_rates.max().unwrap_or_default(),
+            MergeMode::Average => {
+                let count = fresh.len() as u32;
+                (heart_rates.sum::<u32>() + count / 2) / count
+            }
+        };
+        Some(merged as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALENESS: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn average_combines_fresh_readings() {
+        let start = Instant::now();
+        let mut merger = Merger::new(MergeMode::Average, STALENESS);
+        merger.update(1, 81, start);
+        assert_eq!(merger.update(0, 70, start), Some(76));
+    }
+
+    #[test]
+    fn max_combines_fresh_readings() {
+        let start = Instant::now();
+        let mut merger = Merger::new(MergeMode::Max, STALENESS);
+        merger.update(1, 81, start);
+        assert_eq!(merger.update(0, 70, start), Some(81));
+    }
+
+    #[test]
+    fn stale_reading_is_left_out() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(10);
+        let mut average = Merger::new(MergeMode::Average, STALENESS);
+        average.update(1, 120, start);
+        assert_eq!(average.update(0, 70, later), Some(70));
+        let mut max = Merger::new(MergeMode::Max, STALENESS);
+        max.update(1, 120, start);
+        assert_eq!(max.update(0, 70, later), Some(70));
+    }
+
+    #[test]
+    fn only_first_fresh_device_drives_output() {
+        let start = Instant::now();
+        let mut merger = Merger::new(MergeMode::Average, STALENESS);
+        assert_eq!(merger.update(0, 70, start), Some(70));
+        assert_eq!(merger.update(1, 80, start), None);
+        // The backup drives once the primary is stale.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(merger.update(1, 90, later), Some(90));
+    }
+
+    #[test]
+    fn separate_passes_every_reading() {
+        let start = Instant::now();
+        let mut merger = Merger::new(MergeMode::Separate, STALENESS);
+        merger.update(0, 70, start);
+        assert_eq!(merger.update(1, 80, start), Some(80));
+    }
+}
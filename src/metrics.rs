@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::fmt::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Counters and gauges of a session, for monitoring long-running ones.
+#[derive(Clone, Default, Debug)]
+pub struct Metrics {
+    pub reconnects: u64,
+    pub notifications: u64,
+    pub osc_send_errors: u64,
+    pub heart_rate: Option<u8>,
+    pub battery: Option<u8>,
+    /// Unix time of the last notification, in seconds.
+    pub last_seen: Option<i64>,
+}
+
+/// Serves the metrics sent on the returned channel in the Prometheus text
+/// format on /metrics.
+pub async fn spawn(port: u16) -> Result<watch::Sender<Metrics>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Serving metrics on port {}", port);
+
+    let (sender, receiver) = watch::channel(Metrics::default());
+    tokio::spawn(async move {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    info!("Failed to accept metrics client: {}", error);
+                    continue;
+                }
+            };
+            let metrics = receiver.borrow().clone();
+            tokio::spawn(async move {
+                if let Err(error) = serve_client(stream, &metrics).await {
+                    info!("Failed to serve the metrics to {}: {}", address, error);
+                }
+            });
+        }
+    });
+
+    Ok(sender)
+}
+
+async fn serve_client(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    let mut request = [0; 1024];
+    let length = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..length]);
+    stream
+        .write_all(format_response(&request, metrics).as_bytes())
+        .await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+pub fn format_response(request: &str, metrics: &Metrics) -> String {
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", format_metrics(metrics)),
+        _ => ("404 Not Found", String::from("Not found, try /metrics\n")),
+    };
+    format!(
+        "HTTP/1.1 {}\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// The metrics in the Prometheus text format, leaving out the gauges that
+/// aren't known yet.
+pub fn format_metrics(metrics: &Metrics) -> String {
+    let counters = [
+        (
+            "vrc_sa_reconnects_total",
+            "Reconnections to the primary peripheral",
+            metrics.reconnects,
+        ),
+        (
+            "vrc_sa_notifications_total",
+            "Heart rate notifications received",
+            metrics.notifications,
+        ),
+        (
+            "vrc_sa_osc_send_errors_total",
+            "OSC messages dropped after retrying",
+            metrics.osc_send_errors,
+        ),
+    ];
+    let gauges = [
+        (
+            "vrc_sa_heart_rate_bpm",
+            "Latest heart rate",
+            metrics.heart_rate.map(i64::from),
+        ),
+        (
+            "vrc_sa_battery_percent",
+            "Latest battery level",
+            metrics.battery.map(i64::from),
+        ),
+        (
+            "vrc_sa_last_seen_timestamp_seconds",
+            "Unix time of the latest notification",
+            metrics.last_seen,
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in counters {
+        let _ = write!(
+            text,
+            "# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n",
+            name, help, value
+        );
+    }
+    for (name, help, value) in gauges {
+        if let Some(value) = value {
+            let _ = write!(
+                text,
+                "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2}\n",
+                name, help, value
+            );
+        }
+    }
+    text
+}
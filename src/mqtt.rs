@@ -0,0 +1,114 @@
+use crate::sample::Sample;
+use crate::sink::LogSink;
+use anyhow::{anyhow, Result};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use std::time::Duration;
+use tokio::time;
+use tracing::info;
+
+const DEFAULT_PORT: u16 = 1883;
+const CLIENT_ID: &str = "vrc_sa_node";
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Splits `mqtt://host:port`, `host:port` or `host` into a host and port.
+pub fn parse_broker_url(url: &str) -> Result<(String, u16)> {
+    let address = url.strip_prefix("mqtt://").unwrap_or(url);
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse()?),
+        None => (address, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(anyhow!("MQTT URL {} has no host", url));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Publishes samples as JSON to an MQTT topic, reconnecting to the broker as needed.
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl MqttSink {
+    pub fn connect(url: &str, topic: String) -> Result<Self> {
+        let (host, port) = parse_broker_url(url)?;
+        let mut options = MqttOptions::new(CLIENT_ID, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut event_loop) = AsyncClient::new(options, 16);
+        tokio::spawn(async move {
+            loop {
+                // Polling again after an error reconnects.
+                if let Err(error) = event_loop.poll().await {
+                    info!("MQTT connection error, reconnecting: {}", error);
+                    time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        });
+        Ok(Self { client, topic })
+    }
+}
+
+impl LogSink for MqttSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()> {
+        let payload = sample.to_json(battery_level);
+        // Samples published while the broker is unreachable are dropped.
+        if let Err(error) = self
+            .client
+            .try_publish(&self.topic, QoS::AtMostOnce, false, payload)
+        {
+            info!("Dropped MQTT message: {}", error);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn payload_has_the_heart_rate_and_battery() {
+        let sample = Sample {
+            timestamp: String::from("2026-01-01T00:00:00+00:00"),
+            heart_rate: 72,
+            rr_intervals: vec![830],
+            contact: Some(true),
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        };
+        let payload: Value = serde_json::from_str(&sample.to_json(Some(85))).unwrap();
+        let expected = json!({
+            "timestamp": "2026-01-01T00:00:00+00:00",
+            "heart_rate": 72,
+            "battery_level": 85,
+            "rr_intervals": [830],
+            "contact": true,
+            "device": 1,
+            "energy_expended": null,
+            "hrv": null,
+        });
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn broker_urls() {
+        let broker = |host: &str, port| (String::from(host), port);
+        assert_eq!(
+            parse_broker_url("mqtt://home:1884/").unwrap(),
+            broker("home", 1884)
+        );
+        assert_eq!(
+            parse_broker_url("home").unwrap(),
+            broker("home", DEFAULT_PORT)
+        );
+        assert!(parse_broker_url("mqtt://:1883").is_err());
+        assert!(parse_broker_url("home:port").is_err());
+    }
+}
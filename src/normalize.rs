@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+const LOW_PERCENTILE: f32 = 0.05;
+const HIGH_PERCENTILE: f32 = 0.95;
+const MIN_SAMPLES: usize = 10;
+/// Beats per minute added below and above a seeded range.
+const SEED_PADDING: u16 = 5;
+
+/// Maps a heart rate from [min, max] to [0.0, 1.0], clamping outside of it.
+pub fn normalize_between(heart_rate: u16, min: u16, max: u16) -> f32 {
+    if max <= min {
+        return 0.5;
+    }
+    let (low, high) = (f32::from(min), f32::from(max));
+    ((f32::from(heart_rate) - low) / (high - low)).clamp(0.0, 1.0)
+}
+
+/// The rise of a heart rate above the resting heart rate, as a fraction of
+/// `delta_max` clamped to [0.0, 1.0].
+pub fn baseline_delta(heart_rate: u8, resting: u8, delta_max: u8) -> f32 {
+    let rise = f32::from(heart_rate.saturating_sub(resting));
+    (rise / f32::from(delta_max.max(1))).min(1.0)
+}
+
+/// Normalizes heart rates against the 5th and 95th percentile of a window of
+/// recent heart rates, so occasional spikes don't skew the range.
+pub struct PercentileRange {
+    capacity: usize,
+    window: VecDeque<u16>,
+    counts: BTreeMap<u16, usize>,
+}
+
+impl PercentileRange {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            window: VecDeque::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, heart_rate: u16) {
+        if self.window.len() == self.capacity {
+            if let Some(oldest) = self.window.pop_front() {
+                if let Some(count) = self.counts.get_mut(&oldest) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(&oldest);
+                    }
+                }
+            }
+        }
+        self.window.push_back(heart_rate);
+        *self.counts.entry(heart_rate).or_insert(0) += 1;
+    }
+
+    pub fn percentile(&self, percentile: f32) -> Option<u16> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let rank = ((self.window.len() - 1) as f32 * percentile).round() as usize;
+        let mut seen = 0;
+        for (heart_rate, count) in &self.counts {
+            seen += count;
+            if seen > rank {
+                return Some(*heart_rate);
+            }
+        }
+        None
+    }
+
+    /// Returns `None` until enough heart rates have been seen.
+    pub fn normalize(&self, heart_rate: u16) -> Option<f32> {
+        if self.window.len() < MIN_SAMPLES {
+            return None;
+        }
+        let low = f32::from(self.percentile(LOW_PERCENTILE)?);
+        let high = f32::from(self.percentile(HIGH_PERCENTILE)?);
+        if high <= low {
+            return Some(0.5);
+        }
+        Some(((f32::from(heart_rate) - low) / (high - low)).clamp(0.0, 1.0))
+    }
+}
+
+/// Observes the heart rate for a while, then normalizes against the padded
+/// minimum and maximum seen during that time for the rest of the session.
+pub struct SeededRange {
+    duration: Duration,
+    started_at: Option<Instant>,
+    observed: Option<(u16, u16)>,
+    locked: Option<(u16, u16)>,
+}
+
+impl SeededRange {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            started_at: None,
+            observed: None,
+            locked: None,
+        }
+    }
+
+    /// Returns `None` while the range is still being observed.
+    pub fn normalize(&mut self, heart_rate: u16, now: Instant) -> Option<f32> {
+        if self.locked.is_none() {
+            let started_at = *self.started_at.get_or_insert(now);
+            if now.duration_since(started_at) < self.duration {
+                self.observed = Some(match self.observed {
+                    Some((low, high)) => (low.min(heart_rate), high.max(heart_rate)),
+                    None => (heart_rate, heart_rate),
+                });
+                return None;
+            }
+            let (low, high) = self.observed.unwrap_or((heart_rate, heart_rate));
+            let range = (
+                low.saturating_sub(SEED_PADDING),
+                high.saturating_add(SEED_PADDING),
+            );
+            info!("Locked normalization range to {}-{} bpm", range.0, range.1);
+            self.locked = Some(range);
+        }
+        let (low, high) = self.locked?;
+        let (low, high) = (f32::from(low), f32::from(high));
+        Some(((f32::from(heart_rate) - low) / (high - low)).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_window_is_bounded() {
+        let mut range = PercentileRange::new(100);
+        for heart_rate in 0..10_000 {
+            range.push(heart_rate % 200);
+        }
+        assert_eq!(range.window.len(), 100);
+        assert_eq!(range.counts.values().sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn percentiles_of_a_uniform_distribution() {
+        let mut range = PercentileRange::new(1000);
+        assert_eq!(range.percentile(0.5), None);
+        for heart_rate in 100..200 {
+            range.push(heart_rate);
+        }
+        assert_eq!(range.percentile(0.0), Some(100));
+        assert_eq!(range.percentile(LOW_PERCENTILE), Some(105));
+        assert_eq!(range.percentile(0.5), Some(150));
+        assert_eq!(range.percentile(HIGH_PERCENTILE), Some(194));
+        assert_eq!(range.percentile(1.0), Some(199));
+    }
+
+    #[test]
+    fn occasional_spikes_do_not_skew_the_range() {
+        let mut range = PercentileRange::new(1000);
+        for heart_rate in 60..69 {
+            range.push(heart_rate);
+        }
+        assert_eq!(range.normalize(60), None);
+        let mut range = PercentileRange::new(1000);
+        for _ in 0..10 {
+            for heart_rate in 60..80 {
+                range.push(heart_rate);
+            }
+        }
+        range.push(220);
+        range.push(30);
+        assert_eq!(range.percentile(LOW_PERCENTILE), Some(60));
+        assert_eq!(range.percentile(HIGH_PERCENTILE), Some(79));
+        assert_eq!(range.normalize(220), Some(1.0));
+        assert_eq!(range.normalize(30), Some(0.0));
+    }
+
+    #[test]
+    fn seeded_range_locks_after_the_window() {
+        let start = Instant::now();
+        let mut range = SeededRange::new(Duration::from_secs(60));
+        for (seconds, heart_rate) in [(0, 70), (20, 90), (40, 80)] {
+            let now = start + Duration::from_secs(seconds);
+            assert_eq!(range.normalize(heart_rate, now), None);
+        }
+        let locked = start + Duration::from_secs(60);
+        assert_eq!(range.normalize(65, locked), Some(0.0));
+        assert_eq!(range.normalize(80, locked), Some(0.5));
+        // Later heart rates no longer move the range.
+        let later = locked + Duration::from_secs(600);
+        assert_eq!(range.normalize(150, later), Some(1.0));
+        assert_eq!(range.normalize(95, later), Some(1.0));
+        assert_eq!(range.normalize(80, later), Some(0.5));
+    }
+
+    #[test]
+    fn baseline_delta_is_the_clamped_rise_above_rest() {
+        assert_eq!(baseline_delta(60, 60, 40), 0.0);
+        assert_eq!(baseline_delta(50, 60, 40), 0.0);
+        assert_eq!(baseline_delta(70, 60, 40), 0.25);
+        assert_eq!(baseline_delta(100, 60, 40), 1.0);
+        assert_eq!(baseline_delta(180, 60, 40), 1.0);
+        assert_eq!(baseline_delta(61, 60, 0), 1.0);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::presence::LaunchGate;
+use anyhow::{bail, Context, Result};
+use rosc::{encoder, OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net;
+use tokio::time;
+use tracing::info;
+
+#[cfg(target_os = "linux")]
+const ENOBUFS: i32 = 105;
+#[cfg(target_os = "macos")]
+const ENOBUFS: i32 = 55;
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055;
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const ENOBUFS: i32 = -1;
+
+/// Resolves a `host:port` address, like `localhost:9000` or `[::1]:9000`,
+/// preferring IPv4 as that's what VRChat listens on.
+pub async fn resolve(address: &str) -> Result<SocketAddr> {
+    let resolved: Vec<_> = net::lookup_host(address)
+        .await
+        .with_context(|| format!("Failed to resolve {}", address))?
+        .collect();
+    let preferred = resolved.iter().find(|resolved| resolved.is_ipv4());
+    let Some(resolved) = preferred.or(resolved.first()).copied() else {
+        bail!("{} doesn't resolve to any address", address);
+    };
+    if resolved.to_string() != address {
+        info!("Resolved {} to {}", address, resolved);
+    }
+    Ok(resolved)
+}
+
+/// Whether a failed send is worth retrying shortly after.
+pub fn is_retryable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    ) || error.raw_os_error() == Some(ENOBUFS)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+/// Sends until it succeeds, fails with an error that isn't worth retrying or
+/// has been retried as often as the policy allows, returning the retries.
+async fn send_with_retries<F>(
+    policy: RetryPolicy,
+    mut send: F,
+) -> std::result::Result<u32, (u32, io::Error)>
+where
+    F: FnMut() -> io::Result<usize>,
+{
+    let mut retries = 0;
+    loop {
+        match send() {
+            Ok(_) => break Ok(retries),
+            Err(error) if is_retryable(&error) && retries < policy.retries => {
+                retries += 1;
+                time::sleep(policy.delay).await;
+            }
+            Err(error) => break Err((retries, error)),
+        }
+    }
+}
+
+/// Checks that an address is something OSC receivers will accept.
+pub fn validate_address(address: &str) -> Result<()> {
+    if !address.starts_with('/') {
+        bail!("OSC address {:?} has to start with /", address);
+    }
+    if let Some(character) = address
+        .chars()
+        .find(|character| " #*,?[]{}".contains(*character) || character.is_control())
+    {
+        bail!(
+            "OSC address {:?} contains the reserved character {:?}",
+            address,
+            character
+        );
+    }
+    Ok(())
+}
+
+/// The battery level of the combined message while it's unknown.
+pub const UNKNOWN_BATTERY: f32 = -1.0;
+
+/// Arguments of the combined message: the heart rate, the normalized heart
+/// rate and the battery level in [0.0, 1.0], or -1.0 while it's unknown.
+pub fn combined_arguments(
+    beats_per_minute: u8,
+    normalized: f32,
+    battery_level: Option<u8>,
+) -> Vec<OscType> {
+    let battery = battery_level.map_or(UNKNOWN_BATTERY, |level| f32::from(level) / 100.0);
+    vec![
+        OscType::Int(i32::from(beats_per_minute)),
+        OscType::Float(normalized),
+        OscType::Float(battery),
+    ]
+}
+
+/// Clamps every float argument to [min, max], except an unknown battery
+/// level at the `battery` index.
+pub fn clamp_floats(arguments: &mut [OscType], min: f32, max: f32, battery: Option<usize>) {
+    for (index, argument) in arguments.iter_mut().enumerate() {
+        if let OscType::Float(value) = argument {
+            if Some(index) != battery || *value != UNKNOWN_BATTERY {
+                *value = value.clamp(min, max);
+            }
+        }
+    }
+}
+
+/// Clamps the parameters that are meant to be in [0.0, 1.0] to a range, in
+/// case the math of a feature goes wrong. Others, like the smoothed heart
+/// rate, are sent as computed.
+pub struct OutputClamp {
+    min: f32,
+    max: f32,
+    addresses: HashSet<String>,
+    /// The --combined-message address, whose battery level may be unknown.
+    combined_message: Option<String>,
+}
+
+impl OutputClamp {
+    pub fn new(min: f32, max: f32, combined_message: Option<String>) -> Self {
+        Self {
+            min,
+            max,
+            addresses: HashSet::new(),
+            combined_message,
+        }
+    }
+
+    pub fn apply(&self, address: &str, arguments: &mut [OscType]) {
+        if self.addresses.contains(address) {
+            let combined = self.combined_message.as_deref() == Some(address);
+            let battery = combined.then_some(2);
+            clamp_floats(arguments, self.min, self.max, battery);
+        }
+    }
+}
+
+pub struct OscSender {
+    socket: UdpSocket,
+    receivers: Vec<SocketAddr>,
+    launch_gate: Option<LaunchGate>,
+    retry_policy: RetryPolicy,
+    output_clamp: OutputClamp,
+    bundling: bool,
+    bundle: Mutex<Option<Vec<OscMessage>>>,
+    retried: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl OscSender {
+    pub fn new(
+        socket: UdpSocket,
+        receivers: Vec<SocketAddr>,
+        launch_gate: Option<LaunchGate>,
+        retry_policy: RetryPolicy,
+        output_clamp: OutputClamp,
+        bundling: bool,
+    ) -> Self {
+        Self {
+            socket,
+            receivers,
+            launch_gate,
+            retry_policy,
+            output_clamp,
+            bundling,
+            bundle: Mutex::new(None),
+            retried: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds parameters in [0.0, 1.0] to clamp.
+    pub fn clamp(&mut self, addresses: impl IntoIterator<Item = String>) {
+        self.output_clamp.addresses.extend(addresses);
+    }
+
+    /// Messages dropped for a receiver so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends a message to every receiver, retrying on transient errors and
+    /// dropping it for a receiver on others.
+    pub async fn send(&self, address: &str, argument: OscType) -> Result<()> {
+        self.send_arguments(address, vec![argument]).await
+    }
+
+    pub async fn send_arguments(&self, address: &str, mut arguments: Vec<OscType>) -> Result<()> {
+        if let Some(launch_gate) = &self.launch_gate {
+            if !launch_gate.allows(Instant::now()) {
+                return Ok(());
+            }
+        }
+        self.output_clamp.apply(address, &mut arguments);
+
+        let message = OscMessage {
+            addr: String::from(address),
+            args: arguments,
+        };
+        if let Some(bundle) = self.lock_bundle().as_mut() {
+            bundle.push(message);
+            return Ok(());
+        }
+        self.send_packet(&OscPacket::Message(message)).await
+    }
+
+    /// Sends packets like [`Self::send_arguments`], flattening bundles into
+    /// their messages.
+    pub async fn send_packets(&self, mut packets: Vec<OscPacket>) -> Result<()> {
+        packets.reverse();
+        while let Some(packet) = packets.pop() {
+            match packet {
+                OscPacket::Message(message) => {
+                    self.send_arguments(&message.addr, message.args).await?
+                }
+                OscPacket::Bundle(bundle) => packets.extend(bundle.content.into_iter().rev()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the messages sent from now on into a bundle, unless bundling
+    /// is disabled.
+    pub fn start_bundle(&self) {
+        if self.bundling {
+            *self.lock_bundle() = Some(vec![]);
+        }
+    }
+
+    /// Sends the messages collected since [`Self::start_bundle`] as a single
+    /// bundle timetagged with the current time.
+    pub async fn finish_bundle(&self) -> Result<()> {
+        let Some(messages) = self.lock_bundle().take() else {
+            return Ok(());
+        };
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let bundle = OscPacket::Bundle(OscBundle {
+            timetag: OscTime::try_from(SystemTime::now())?,
+            content: messages.into_iter().map(OscPacket::Message).collect(),
+        });
+        self.send_packet(&bundle).await
+    }
+
+    fn lock_bundle(&self) -> MutexGuard<'_, Option<Vec<OscMessage>>> {
+        // The bundle is only ever replaced or pushed to, so a poisoned lock
+        // still holds a usable bundle.
+        self.bundle
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    async fn send_packet(&self, message: &OscPacket) -> Result<()> {
+        let buffer = encoder::encode(message)?;
+        for receiver in &self.receivers {
+            self.send_buffer(&buffer, message, receiver).await;
+        }
+        Ok(())
+    }
+
+    async fn send_buffer(&self, buffer: &[u8], message: &OscPacket, receiver: &SocketAddr) {
+        let sent = send_with_retries(self.retry_policy, || self.socket.send_to(buffer, receiver));
+        let retries = match sent.await {
+            Ok(retries) => retries,
+            Err((retries, error)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                info!(
+                    "Dropped message to host [{}] after {} retries ({} dropped so far): {}",
+                    receiver, retries, dropped, error
+                );
+                return;
+            }
+        };
+        if retries > 0 {
+            let retried = self.retried.fetch_add(1, Ordering::Relaxed) + 1;
+            info!(
+                "Sent message to host [{}] after {} retries ({} retried so far)",
+                receiver, retries, retried
+            );
+        }
+        info!("Sent message to host [{}]: {:?}", receiver, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clamp() -> OutputClamp {
+        let mut clamp = OutputClamp::new(0.0, 1.0, Some(String::from("/combined")));
+        clamp
+            .addresses
+            .insert(String::from("/avatar/parameters/HeartRate"));
+        clamp.addresses.insert(String::from("/combined"));
+        clamp
+    }
+
+    #[test]
+    fn clamps_out_of_range_values_before_sending() {
+        let mut arguments = vec![OscType::Float(1.3)];
+        clamp().apply("/avatar/parameters/HeartRate", &mut arguments);
+        assert_eq!(arguments, vec![OscType::Float(1.0)]);
+        let mut arguments = vec![OscType::Float(-0.2)];
+        clamp().apply("/avatar/parameters/HeartRate", &mut arguments);
+        assert_eq!(arguments, vec![OscType::Float(0.0)]);
+        let mut arguments = vec![OscType::Float(UNKNOWN_BATTERY)];
+        clamp().apply("/avatar/parameters/HeartRate", &mut arguments);
+        assert_eq!(arguments, vec![OscType::Float(0.0)]);
+    }
+
+    #[test]
+    fn leaves_other_parameters_alone() {
+        let mut arguments = vec![OscType::Float(72.4)];
+        clamp().apply("/avatar/parameters/HeartRateSmoothed", &mut arguments);
+        assert_eq!(arguments, vec![OscType::Float(72.4)]);
+    }
+
+    #[test]
+    fn keeps_the_unknown_battery_of_the_combined_message() {
+        let mut arguments = combined_arguments(180, 1.2, None);
+        clamp().apply("/combined", &mut arguments);
+        let expected = vec![
+            OscType::Int(180),
+            OscType::Float(1.0),
+            OscType::Float(UNKNOWN_BATTERY),
+        ];
+        assert_eq!(arguments, expected);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        assert!(is_retryable(&io::ErrorKind::WouldBlock.into()));
+        assert!(is_retryable(&io::ErrorKind::Interrupted.into()));
+        assert!(is_retryable(&io::Error::from_raw_os_error(ENOBUFS)));
+        assert!(!is_retryable(&io::ErrorKind::ConnectionRefused.into()));
+        assert!(!is_retryable(&io::ErrorKind::PermissionDenied.into()));
+    }
+
+    /// Sends with a policy of two retries, failing with the given errors
+    /// before succeeding, and returns the outcome and the attempts made.
+    async fn send(failures: &[io::ErrorKind]) -> (std::result::Result<u32, u32>, usize) {
+        let policy = RetryPolicy {
+            retries: 2,
+            delay: Duration::ZERO,
+        };
+        let mut attempts = 0;
+        let sent = send_with_retries(policy, || {
+            attempts += 1;
+            match failures.get(attempts - 1) {
+                Some(kind) => Err((*kind).into()),
+                None => Ok(0),
+            }
+        });
+        let outcome = sent.await.map_err(|(retries, _)| retries);
+        (outcome, attempts)
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_up_to_the_policy() {
+        use io::ErrorKind::{ConnectionRefused, WouldBlock};
+        assert_eq!(send(&[]).await, (Ok(0), 1));
+        assert_eq!(send(&[WouldBlock, WouldBlock]).await, (Ok(2), 3));
+        assert_eq!(
+            send(&[WouldBlock, WouldBlock, WouldBlock]).await,
+            (Err(2), 3)
+        );
+        assert_eq!(send(&[WouldBlock, ConnectionRefused]).await, (Err(1), 2));
+        assert_eq!(send(&[ConnectionRefused]).await, (Err(0), 1));
+    }
+
+    #[test]
+    fn combined_arguments_are_heart_rate_normalized_and_battery() {
+        let expected = vec![OscType::Int(72), OscType::Float(0.36), OscType::Float(0.85)];
+        assert_eq!(combined_arguments(72, 0.36, Some(85)), expected);
+        let arguments = combined_arguments(72, 0.36, None);
+        assert_eq!(arguments[2], OscType::Float(UNKNOWN_BATTERY));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::device::DeviceEvent;
+use crate::peripheral::{
+    matches_name, PeripheralTarget, HEART_RATE_CHARACTERISTIC_UUID, HEART_RATE_SERVICE_UUID,
+};
+use anyhow::{bail, Result};
+use btleplug::api::{Central, CentralEvent, Peripheral as _, ScanFilter, ValueNotification};
+use btleplug::platform::Adapter;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::info;
+use uuid::Uuid;
+
+const DETECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns the heart rate measurement advertised as service data, if any.
+pub fn heart_rate_service_data(service_data: &HashMap<Uuid, Vec<u8>>) -> Option<&[u8]> {
+    service_data
+        .get(&HEART_RATE_SERVICE_UUID)
+        .map(Vec::as_slice)
+        .filter(|value| value.len() >= 2)
+}
+
+/// Streams heart rate from advertisements instead of connecting to the
+/// peripheral, which counts as disconnected after `threshold` without any.
+pub async fn run(
+    device: usize,
+    adapter: &Adapter,
+    target: &PeripheralTarget,
+    threshold: Duration,
+    events: &mpsc::Sender<DeviceEvent>,
+) -> Result<()> {
+    let mut adapter_events = adapter.events().await?;
+    adapter.start_scan(ScanFilter::default()).await?;
+    info!("Listening for heart rate advertisements");
+
+    let mut found = None;
+    let mut streaming = false;
+    let detection_deadline = time::sleep(DETECTION_TIMEOUT);
+    tokio::pin!(detection_deadline);
+    let silence_deadline = time::sleep(threshold);
+    tokio::pin!(silence_deadline);
+    loop {
+        tokio::select! {
+            Some(event) = adapter_events.next() => {
+                let CentralEvent::ServiceDataAdvertisement { id, service_data } = event else {
+                    continue;
+                };
+                let peripheral = adapter.peripheral(&id).await?;
+                let Some(properties) = peripheral.properties().await? else {
+                    continue;
+                };
+                let matches = match target {
+                    PeripheralTarget::Address(address) | PeripheralTarget::Remembered(address) => {
+                        properties.address == *address
+                    }
+                    PeripheralTarget::Name(name) => {
+                        matches_name(properties.local_name.as_deref(), name)
+                    }
+                    PeripheralTarget::Index(_) | PeripheralTarget::Interactive => true,
+                };
+                if !matches || found.is_some_and(|address| address != properties.address) {
+                    continue;
+                }
+                let Some(value) = heart_rate_service_data(&service_data) else {
+                    continue;
+                };
+
+                silence_deadline.as_mut().reset(time::Instant::now() + threshold);
+                if !streaming {
+                    info!(
+                        "Receiving heart rate advertisements from {} [{}]",
+                        properties.local_name.as_deref().unwrap_or("(Empty)"),
+                        properties.address
+                    );
+                    found = Some(properties.address);
+                    streaming = true;
+                    let connected = DeviceEvent::Connected {
+                        device,
+                        address: properties.address,
+                        rssi: properties.rssi,
+                    };
+                    if events.send(connected).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                info!("Received advertised data from {}: {:?}", properties.address, value);
+                let notification = ValueNotification {
+                    uuid: HEART_RATE_CHARACTERISTIC_UUID,
+                    value: value.to_vec(),
+                };
+                let event = DeviceEvent::Notification {
+                    device,
+                    notification,
+                    captured_at: None,
+                };
+                if events.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+            _ = &mut detection_deadline, if found.is_none() => {
+                bail!(
+                    "No heart rate advertisements received within {:?}, the peripheral \
+                     probably doesn't broadcast heart rate; run without --passive",
+                    DETECTION_TIMEOUT
+                );
+            }
+            _ = &mut silence_deadline, if streaming => {
+                info!("No heart rate advertisements for {:?}", threshold);
+                streaming = false;
+                if events.send(DeviceEvent::Disconnected { device }).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heart_rate_is_read_from_service_data() {
+        let service_data = HashMap::from([(HEART_RATE_SERVICE_UUID, vec![0x00, 72])]);
+        let value = heart_rate_service_data(&service_data).unwrap();
+        let measurement = crate::measurement::parse_heart_rate_measurement(value).unwrap();
+        assert_eq!(measurement.heart_rate, 72);
+    }
+
+    #[test]
+    fn other_or_short_service_data_is_ignored() {
+        let battery_service = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+        let other = HashMap::from([(battery_service, vec![0x00, 72])]);
+        assert_eq!(heart_rate_service_data(&other), None);
+        let short = HashMap::from([(HEART_RATE_SERVICE_UUID, vec![0x00])]);
+        assert_eq!(heart_rate_service_data(&short), None);
+    }
+}
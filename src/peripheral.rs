@@ -0,0 +1,1268 @@
+use crate::backoff::Backoff;
+use crate::prompt;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, CharPropFlags, Characteristic, Peripheral as _,
+    PeripheralProperties, ScanFilter, ValueNotification,
+};
+use btleplug::platform::{Adapter, Peripheral};
+use clap::ValueEnum;
+use dialoguer::{theme::ColorfulTheme, Select};
+use futures::future::join_all;
+use futures::{Future, Stream, StreamExt};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time;
+use tracing::{info, warn};
+use uuid::{uuid, Uuid};
+
+pub const BATTERY_LEVEL_CHARACTERISTIC_UUID: Uuid = uuid!("00002a19-0000-1000-8000-00805f9b34fb");
+pub const HEART_RATE_SERVICE_UUID: Uuid = uuid!("0000180d-0000-1000-8000-00805f9b34fb");
+pub const HEART_RATE_CHARACTERISTIC_UUID: Uuid = uuid!("00002a37-0000-1000-8000-00805f9b34fb");
+/// How long to look for the remembered peripheral before showing the menu.
+const REMEMBERED_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+/// btleplug leaves the MTU exchange to the platform stack on every platform,
+/// which negotiates one itself.
+pub const MTU_REQUESTS_SUPPORTED: bool = false;
+/// Nor does it expose the connection parameters, which the stack picks.
+pub const CONNECTION_PARAMETER_REQUESTS_SUPPORTED: bool = false;
+
+#[async_trait]
+pub trait AdapterExt {
+    async fn scan_for(&self, duration: Duration) -> Result<()>;
+    async fn scan_for_addresses(&self, duration: Duration) -> Result<Vec<BDAddr>>;
+    /// Scans until the peripheral shows up, checking every `duration`.
+    async fn scan_for_peripheral(&self, address: BDAddr, duration: Duration) -> Result<Peripheral>;
+    async fn scan_for_named_peripheral(&self, name: &str, duration: Duration)
+        -> Result<Peripheral>;
+    async fn matching_peripheral_rssis(&self, name: &str) -> Result<Vec<(BDAddr, i16)>>;
+}
+
+#[async_trait]
+impl AdapterExt for Adapter {
+    async fn scan_for(&self, duration: Duration) -> Result<()> {
+        let filter = ScanFilter::default();
+
+        self.start_scan(filter).await?;
+        time::sleep(duration).await;
+        self.stop_scan().await?;
+
+        Ok(())
+    }
+
+    async fn scan_for_addresses(&self, duration: Duration) -> Result<Vec<BDAddr>> {
+        let mut events = self.events().await?;
+        let filter = ScanFilter::default();
+        let deadline = time::sleep(duration);
+        tokio::pin!(deadline);
+
+        let mut ids = vec![];
+        self.start_scan(filter).await?;
+        loop {
+            tokio::select! {
+                Some(event) = events.next() => {
+                    let id = match event {
+                        CentralEvent::DeviceDiscovered(id)
+                        | CentralEvent::DeviceUpdated(id)
+                        | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+                        | CentralEvent::ServiceDataAdvertisement { id, .. }
+                        | CentralEvent::ServicesAdvertisement { id, .. } => id,
+                        _ => continue,
+                    };
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+                _ = &mut deadline => break,
+            }
+        }
+        self.stop_scan().await?;
+
+        let mut addresses = vec![];
+        for id in ids {
+            addresses.push(self.peripheral(&id).await?.address());
+        }
+        Ok(addresses)
+    }
+
+    async fn scan_for_peripheral(&self, address: BDAddr, duration: Duration) -> Result<Peripheral> {
+        info!("Scanning for peripheral with address {}", address);
+
+        let filter = ScanFilter::default();
+
+        self.start_scan(filter).await?;
+        let peripheral = loop {
+            time::sleep(duration).await;
+            let peripherals = self.peripherals().await?;
+            let maybe_peripheral = peripherals
+                .iter()
+                .find(|peripheral| peripheral.address() == address);
+            match maybe_peripheral {
+                Some(peripheral) => break peripheral.clone(),
+                None => continue,
+            }
+        };
+        self.stop_scan().await?;
+
+        info!("Peripheral with address {} found", address);
+
+        Ok(peripheral)
+    }
+
+    async fn scan_for_named_peripheral(
+        &self,
+        name: &str,
+        duration: Duration,
+    ) -> Result<Peripheral> {
+        info!("Scanning for peripheral named {}", name);
+
+        let filter = ScanFilter::default();
+
+        self.start_scan(filter).await?;
+        let peripheral = 'scan: loop {
+            time::sleep(duration).await;
+            for peripheral in self.peripherals().await? {
+                let local_name = peripheral
+                    .properties()
+                    .await?
+                    .and_then(|properties| properties.local_name);
+                if matches_name(local_name.as_deref(), name) {
+                    break 'scan peripheral;
+                }
+            }
+        };
+        self.stop_scan().await?;
+
+        info!("Peripheral named {} found", name);
+
+        Ok(peripheral)
+    }
+
+    async fn matching_peripheral_rssis(&self, name: &str) -> Result<Vec<(BDAddr, i16)>> {
+        let mut rssis = vec![];
+        for peripheral in self.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            if let Some(rssi) = properties.rssi {
+                if matches_name(properties.local_name.as_deref(), name) {
+                    rssis.push((properties.address, rssi));
+                }
+            }
+        }
+        Ok(rssis)
+    }
+}
+
+pub fn matches_name(local_name: Option<&str>, name: &str) -> bool {
+    local_name.is_some_and(|local_name| local_name.to_lowercase().contains(&name.to_lowercase()))
+}
+
+/// A trade-off between latency and battery life of the connection.
+#[derive(ValueEnum, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionInterval {
+    LowLatency,
+    Balanced,
+    PowerSave,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConnectionParameters {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    /// Connection events the peripheral may skip.
+    pub latency: u16,
+    pub supervision_timeout: Duration,
+}
+
+impl ConnectionInterval {
+    pub fn parameters(self) -> ConnectionParameters {
+        let (min_interval, max_interval, latency, supervision_timeout) = match self {
+            Self::LowLatency => (7_500, 15_000, 0, 2_000),
+            Self::Balanced => (30_000, 50_000, 0, 4_000),
+            Self::PowerSave => (100_000, 125_000, 4, 6_000),
+        };
+        ConnectionParameters {
+            min_interval: Duration::from_micros(min_interval),
+            max_interval: Duration::from_micros(max_interval),
+            latency,
+            supervision_timeout: Duration::from_millis(supervision_timeout),
+        }
+    }
+}
+
+/// Picks an adapter by its index, or by a substring of its information that
+/// only one adapter matches.
+pub fn match_adapter(adapter_infos: &[String], selector: &str) -> Result<usize> {
+    if let Ok(index) = selector.parse::<usize>() {
+        if index >= adapter_infos.len() {
+            bail!(
+                "Adapter index {} is out of range, found {} adapters",
+                index,
+                adapter_infos.len()
+            );
+        }
+        return Ok(index);
+    }
+    let matching: Vec<_> = adapter_infos
+        .iter()
+        .enumerate()
+        .filter(|(_, info)| matches_name(Some(info), selector))
+        .collect();
+    match matching.as_slice() {
+        [(index, _)] => Ok(*index),
+        [] => bail!(
+            "No adapter matches {:?}, found {:?}",
+            selector,
+            adapter_infos
+        ),
+        _ => bail!(
+            "Several adapters match {:?}: {:?}",
+            selector,
+            matching.iter().map(|(_, info)| info).collect::<Vec<_>>()
+        ),
+    }
+}
+
+/// What to do about a setting to request from a peripheral after connecting.
+#[derive(PartialEq, Eq, Debug)]
+pub enum LinkRequest<T> {
+    Skip,
+    Request(T),
+    /// It was asked for, but the platform can't request it.
+    Unsupported,
+}
+
+/// Requests a setting only when it's asked for and the platform supports it.
+pub fn link_request<T>(requested: Option<T>, supported: bool) -> LinkRequest<T> {
+    match requested {
+        None => LinkRequest::Skip,
+        Some(requested) if supported => LinkRequest::Request(requested),
+        Some(_) => LinkRequest::Unsupported,
+    }
+}
+
+#[async_trait]
+pub trait PeripheralExt {
+    /// Returns the negotiated MTU.
+    async fn request_mtu(&self, mtu: u16) -> Result<u16>;
+    async fn request_connection_parameters(&self, parameters: ConnectionParameters) -> Result<()>;
+}
+
+#[async_trait]
+impl PeripheralExt for Peripheral {
+    async fn request_mtu(&self, _mtu: u16) -> Result<u16> {
+        // Only called when MTU_REQUESTS_SUPPORTED.
+        Err(anyhow!("MTU requests aren't supported on this platform"))
+    }
+
+    async fn request_connection_parameters(&self, _parameters: ConnectionParameters) -> Result<()> {
+        // Only called when CONNECTION_PARAMETER_REQUESTS_SUPPORTED.
+        Err(anyhow!(
+            "Connection parameter requests aren't supported on this platform"
+        ))
+    }
+}
+
+/// Picks the peripheral at `index`, in the order the interactive menu lists them.
+fn select_by_index<T>(peripherals: Vec<T>, index: usize) -> Result<T> {
+    let count = peripherals.len();
+    match peripherals.into_iter().nth(index) {
+        Some(peripheral) => Ok(peripheral),
+        None => bail!(
+            "Peripheral index {} is out of range, found {} peripherals",
+            index,
+            count
+        ),
+    }
+}
+
+/// Adds newly scanned addresses to the listed ones, keeping the order they
+/// were first seen in and optionally dropping ones missing from the scan.
+fn merge_scan(listed: &[BDAddr], scanned: &[BDAddr], drop_stale: bool) -> Vec<BDAddr> {
+    let mut merged: Vec<BDAddr> = listed
+        .iter()
+        .filter(|address| !drop_stale || scanned.contains(address))
+        .copied()
+        .collect();
+    for address in scanned {
+        if !merged.contains(address) {
+            merged.push(*address);
+        }
+    }
+    merged
+}
+
+/// Whether to show the menu: when the peripheral list changed since it was
+/// last shown, or right after the user asked to scan again.
+fn should_redraw(
+    shown: Option<&[(BDAddr, Option<String>)]>,
+    current: &[(BDAddr, Option<String>)],
+    rescan_requested: bool,
+) -> bool {
+    let Some(shown) = shown.filter(|_| !rescan_requested) else {
+        return true;
+    };
+    let mut shown = shown.to_vec();
+    let mut current = current.to_vec();
+    shown.sort();
+    current.sort();
+    shown != current
+}
+
+/// Whether a peripheral advertises the heart rate service.
+pub fn advertises_heart_rate(properties: Option<&PeripheralProperties>) -> bool {
+    properties.is_some_and(|properties| properties.services.contains(&HEART_RATE_SERVICE_UUID))
+}
+
+/// Formats a menu item like `Polar H10  (-57 dBm)  [AA:BB:CC:DD:EE:FF]`.
+pub fn menu_item(local_name: Option<&str>, rssi: Option<i16>, address: BDAddr) -> String {
+    let local_name = local_name.unwrap_or("(Empty)");
+    match rssi {
+        Some(rssi) => format!("{}  ({} dBm)  [{}]", local_name, rssi, address),
+        None => format!("{}  [{}]", local_name, address),
+    }
+}
+
+/// Orders peripherals the way the menu lists them: those advertising the heart
+/// rate service unless `show_all`, the nearest first and ties in the order
+/// they were first seen in.
+fn menu_order<T: Clone>(
+    mut candidates: Vec<(T, Option<PeripheralProperties>)>,
+    show_all: bool,
+) -> Vec<(T, Option<PeripheralProperties>)> {
+    if !show_all {
+        let heart_rate: Vec<_> = candidates
+            .iter()
+            .filter(|(_, properties)| advertises_heart_rate(properties.as_ref()))
+            .cloned()
+            .collect();
+        if heart_rate.is_empty() {
+            info!("No peripherals advertise the heart rate service, listing all of them");
+        } else {
+            candidates = heart_rate;
+        }
+    }
+    // The nearest peripheral goes first, those without a signal strength last.
+    candidates.sort_by_key(|(_, properties)| {
+        Reverse(properties.as_ref().and_then(|properties| properties.rssi))
+    });
+    candidates
+}
+
+/// Scans and lists the peripherals in menu order, `order` keeping the order
+/// they were first seen in across scans.
+async fn scan_menu_peripherals(
+    adapter: &Adapter,
+    options: &ConnectOptions,
+    order: &mut Vec<BDAddr>,
+) -> Result<Vec<(Peripheral, Option<PeripheralProperties>)>> {
+    let seen = adapter.scan_for_addresses(options.scan_duration).await?;
+
+    let known = adapter.peripherals().await?;
+    let scanned = if options.drop_stale {
+        seen
+    } else {
+        known
+            .iter()
+            .map(|peripheral| peripheral.address())
+            .collect()
+    };
+    *order = merge_scan(order, &scanned, options.drop_stale);
+    let peripherals: Vec<_> = order
+        .iter()
+        .filter_map(|address| {
+            known
+                .iter()
+                .find(|peripheral| peripheral.address() == *address)
+                .cloned()
+        })
+        .collect();
+    if peripherals.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let properties = get_peripheral_properties(&peripherals).await;
+    let candidates = peripherals.into_iter().zip(properties).collect();
+    Ok(menu_order(candidates, options.show_all))
+}
+
+async fn interactive_peripheral_scan(
+    adapter: &Adapter,
+    options: &ConnectOptions,
+) -> Result<Peripheral> {
+    let scan_interval = options.scan_interval;
+    let mut shown: Option<Vec<(BDAddr, Option<String>)>> = None;
+    let mut rescan_requested = false;
+    let mut order = vec![];
+    loop {
+        let candidates = scan_menu_peripherals(adapter, options, &mut order).await?;
+        if candidates.is_empty() {
+            info!("No peripherals found, scanning again");
+            time::sleep(scan_interval).await;
+            continue;
+        }
+        let (peripherals, properties): (Vec<_>, Vec<_>) = candidates.into_iter().unzip();
+        let listed: Vec<_> = peripherals
+            .iter()
+            .map(|peripheral| peripheral.address())
+            .zip(properties.iter().map(|properties| {
+                properties
+                    .as_ref()
+                    .and_then(|properties| properties.local_name.clone())
+            }))
+            .collect();
+        if !should_redraw(shown.as_deref(), &listed, rescan_requested) {
+            info!("No new peripherals found, scanning again");
+            time::sleep(scan_interval).await;
+            continue;
+        }
+
+        let mut peripheral_selection_items = vec![String::from("[Scan again]")];
+        peripheral_selection_items.extend(listed.iter().zip(&properties).map(
+            |((address, local_name), properties)| {
+                let rssi = properties.as_ref().and_then(|properties| properties.rssi);
+                menu_item(local_name.as_deref(), rssi, *address)
+            },
+        ));
+
+        let peripheral_selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select bluetooth peripheral")
+            .default(0)
+            .items(&peripheral_selection_items)
+            .interact_opt();
+        let peripheral_selection = prompt::selection(peripheral_selection)?;
+        rescan_requested = peripheral_selection == 0;
+        if rescan_requested {
+            info!("User chose to scan again");
+            shown = Some(listed);
+            time::sleep(scan_interval).await;
+            continue;
+        }
+
+        // Account for the "scan again" item.
+        let peripheral_index = peripheral_selection - 1;
+
+        match peripherals.get(peripheral_index).cloned() {
+            Some(peripheral) => break Ok(peripheral),
+            None => continue,
+        }
+    }
+}
+
+/// Scans for `duration` and returns the properties of every peripheral
+/// found, the nearest first.
+pub async fn list_peripherals(
+    adapter: &Adapter,
+    duration: Duration,
+) -> Result<Vec<PeripheralProperties>> {
+    adapter.scan_for(duration).await?;
+    let peripherals = adapter.peripherals().await?;
+    let mut properties: Vec<_> = get_peripheral_properties(&peripherals)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    properties.sort_by_key(|properties| Reverse(properties.rssi));
+    Ok(properties)
+}
+
+/// A table of the name, address, signal strength and advertised services
+/// of each peripheral, for --list-devices.
+pub fn device_table(peripherals: &[PeripheralProperties]) -> String {
+    let header = [
+        String::from("Name"),
+        String::from("Address"),
+        String::from("RSSI"),
+        String::from("Services"),
+    ];
+    let rows: Vec<[String; 4]> = peripherals
+        .iter()
+        .map(|properties| {
+            let services: Vec<_> = properties.services.iter().map(Uuid::to_string).collect();
+            [
+                properties
+                    .local_name
+                    .clone()
+                    .unwrap_or(String::from("(Empty)")),
+                properties.address.to_string(),
+                properties
+                    .rssi
+                    .map_or(String::from("-"), |rssi| format!("{} dBm", rssi)),
+                services.join(", "),
+            ]
+        })
+        .collect();
+    let mut widths = [0; 3];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = format!(
+            "{:name$}  {:address$}  {:rssi$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            name = widths[0],
+            address = widths[1],
+            rssi = widths[2],
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    if rows.is_empty() {
+        table.push_str("No peripherals found\n");
+    }
+    table
+}
+
+async fn get_peripheral_properties(
+    peripherals: &[Peripheral],
+) -> Vec<Option<PeripheralProperties>> {
+    join_all(
+        peripherals
+            .iter()
+            .map(|peripheral| async { peripheral.properties().await.ok().flatten() })
+            .collect::<Vec<_>>(),
+    )
+    .await
+}
+
+pub struct ConnectedPeripheral {
+    pub peripheral: Peripheral,
+    pub address: BDAddr,
+    pub name: String,
+    pub rssi: Option<i16>,
+    pub characteristics: PeripheralCharacteristics,
+    pub notification_stream: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PeripheralCharacteristics {
+    pub battery_level: Option<Characteristic>,
+    pub heart_rate: Characteristic,
+}
+
+/// Characteristics discovered on an earlier connection to a peripheral.
+#[derive(Clone, Debug)]
+pub struct CharacteristicCache {
+    pub address: BDAddr,
+    pub characteristics: PeripheralCharacteristics,
+}
+
+#[derive(Clone, Debug)]
+pub enum PeripheralTarget {
+    Address(BDAddr),
+    Name(String),
+    Index(usize),
+    /// The peripheral connected to last, falling back to the menu when it
+    /// can't be found.
+    Remembered(BDAddr),
+    Interactive,
+}
+
+#[derive(Clone)]
+pub struct ConnectOptions {
+    pub mtu: Option<u16>,
+    pub connection_interval: Option<ConnectionInterval>,
+    pub pre_subscribe_delay: Duration,
+    pub keep_scanning: bool,
+    pub scan_interval: Duration,
+    pub scan_duration: Duration,
+    pub scan_timeout: Option<Duration>,
+    pub skip_battery: bool,
+    pub drop_stale: bool,
+    pub show_all: bool,
+    pub discovery_retries: u32,
+    pub discovery_retry_delay: Duration,
+    pub max_reconnect_attempts: Option<u32>,
+    /// The standard characteristics unless overridden for a non-standard
+    /// sensor.
+    pub heart_rate_characteristic: Uuid,
+    pub battery_characteristic: Uuid,
+}
+
+/// A peripheral that can't be streamed from.
+#[derive(Debug)]
+pub enum ConnectError {
+    MissingProperties,
+    MissingHeartRate(String),
+    PeripheralNotFound(String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingProperties => write!(f, "The peripheral has no properties"),
+            Self::MissingHeartRate(name) => {
+                write!(f, "{} has no heart rate characteristic", name)
+            }
+            Self::PeripheralNotFound(target) => write!(f, "Peripheral {} wasn't found", target),
+        }
+    }
+}
+
+impl Error for ConnectError {}
+
+/// Connects to the target, going back to the menu when the user picked a
+/// peripheral that can't be streamed from.
+pub async fn connect_to_peripheral(
+    adapter: &Adapter,
+    target: &PeripheralTarget,
+    options: &ConnectOptions,
+    cache: Option<&CharacteristicCache>,
+) -> Result<ConnectedPeripheral> {
+    let mut target = target;
+    loop {
+        let result = connect_once(adapter, target, options, cache).await;
+        let interactive = matches!(
+            target,
+            PeripheralTarget::Interactive | PeripheralTarget::Remembered(_)
+        );
+        match result {
+            Err(error) if interactive && error.downcast_ref::<ConnectError>().is_some() => {
+                info!("{}, pick another peripheral", error);
+                target = &PeripheralTarget::Interactive;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Gives up on a scan once the timeout expires, instead of scanning forever.
+async fn scan_with_timeout(
+    adapter: &Adapter,
+    timeout: Option<Duration>,
+    target: String,
+    scan: impl Future<Output = Result<Peripheral>>,
+) -> Result<Peripheral> {
+    let Some(timeout) = timeout else {
+        return scan.await;
+    };
+    match time::timeout(timeout, scan).await {
+        Ok(peripheral) => peripheral,
+        Err(_) => {
+            adapter.stop_scan().await?;
+            Err(ConnectError::PeripheralNotFound(target).into())
+        }
+    }
+}
+
+async fn connect_once(
+    adapter: &Adapter,
+    target: &PeripheralTarget,
+    options: &ConnectOptions,
+    cache: Option<&CharacteristicCache>,
+) -> Result<ConnectedPeripheral> {
+    let peripheral = match target {
+        PeripheralTarget::Address(address) => {
+            let scan = adapter.scan_for_peripheral(*address, options.scan_duration);
+            scan_with_timeout(adapter, options.scan_timeout, address.to_string(), scan).await?
+        }
+        PeripheralTarget::Name(name) => {
+            let scan = adapter.scan_for_named_peripheral(name, options.scan_duration);
+            scan_with_timeout(adapter, options.scan_timeout, name.clone(), scan).await?
+        }
+        PeripheralTarget::Index(index) => {
+            let candidates = scan_menu_peripherals(adapter, options, &mut vec![]).await?;
+            let peripherals = candidates
+                .into_iter()
+                .map(|(peripheral, _)| peripheral)
+                .collect();
+            select_by_index(peripherals, *index)?
+        }
+        PeripheralTarget::Remembered(address) => {
+            let scan = adapter.scan_for_peripheral(*address, options.scan_duration);
+            match time::timeout(REMEMBERED_SCAN_TIMEOUT, scan).await {
+                Ok(peripheral) => peripheral?,
+                Err(_) => {
+                    info!("Couldn't find remembered peripheral {}", address);
+                    adapter.stop_scan().await?;
+                    interactive_peripheral_scan(adapter, options).await?
+                }
+            }
+        }
+        PeripheralTarget::Interactive => interactive_peripheral_scan(adapter, options).await?,
+    };
+
+    let peripheral_properties = peripheral
+        .properties()
+        .await?
+        .ok_or(ConnectError::MissingProperties)?;
+    let peripheral_address = peripheral_properties.address;
+    let peripheral_local_name = peripheral_properties
+        .local_name
+        .unwrap_or(String::from("(Empty)"));
+
+    info!(
+        "Connecting to {} [{}]",
+        peripheral_local_name, peripheral_address
+    );
+    let mut backoff = Backoff::new(options.max_reconnect_attempts);
+    while let Err(error) = peripheral.connect().await {
+        let Some(delay) = backoff.next_delay() else {
+            bail!(
+                "Failed to connect to {} after {} attempts: {}",
+                peripheral_local_name,
+                backoff.attempts(),
+                error
+            );
+        };
+        info!(
+            "Failed to connect to {}, retrying in {:?}: {}",
+            peripheral_local_name, delay, error
+        );
+        time::sleep(delay).await;
+    }
+    info!(
+        "Connected to {} [{}]",
+        peripheral_local_name, peripheral_address
+    );
+
+    if let Some(mtu) = options.mtu {
+        match peripheral.request_mtu(mtu).await {
+            Ok(negotiated_mtu) => info!(
+                "Negotiated MTU of {} with {} (requested {})",
+                negotiated_mtu, peripheral_local_name, mtu
+            ),
+            Err(error) => warn!(
+                "Failed to request an MTU of {} from {}: {}",
+                mtu, peripheral_local_name, error
+            ),
+        }
+    }
+
+    if let Some(connection_interval) = options.connection_interval {
+        let parameters = connection_interval.parameters();
+        match peripheral.request_connection_parameters(parameters).await {
+            Ok(()) => info!(
+                "Requested connection parameters {:?} from {}",
+                parameters, peripheral_local_name
+            ),
+            Err(error) => warn!(
+                "Failed to request connection parameters {:?} from {}: {}",
+                parameters, peripheral_local_name, error
+            ),
+        }
+    }
+
+    let cached_characteristics = cache
+        .filter(|cache| cache.address == peripheral_address)
+        .map(|cache| cache.characteristics.clone());
+    let characteristics = subscribe_heart_rate(
+        cached_characteristics,
+        options.pre_subscribe_delay,
+        &peripheral_local_name,
+        |characteristic| {
+            let peripheral = &peripheral;
+            async move { peripheral.subscribe(&characteristic).await }
+        },
+        || discover_characteristics(&peripheral, &peripheral_local_name, options),
+    )
+    .await?;
+
+    if options.keep_scanning {
+        adapter.start_scan(ScanFilter::default()).await?;
+    }
+
+    let notification_stream = peripheral.notifications().await?;
+    Ok(ConnectedPeripheral {
+        peripheral,
+        address: peripheral_address,
+        name: peripheral_local_name,
+        rssi: peripheral_properties.rssi,
+        characteristics,
+        notification_stream,
+    })
+}
+
+/// Subscribes to the heart rate characteristic cached from an earlier
+/// connection, and only discovers the characteristics again when there is
+/// none or subscribing to it fails.
+async fn subscribe_heart_rate<S, SFut, D, DFut>(
+    cached: Option<PeripheralCharacteristics>,
+    delay: Duration,
+    name: &str,
+    subscribe: S,
+    discover: D,
+) -> Result<PeripheralCharacteristics>
+where
+    S: Fn(Characteristic) -> SFut,
+    SFut: Future<Output = btleplug::Result<()>>,
+    D: FnOnce() -> DFut,
+    DFut: Future<Output = Result<PeripheralCharacteristics>>,
+{
+    if let Some(characteristics) = cached {
+        match subscribe(characteristics.heart_rate.clone()).await {
+            Ok(()) => {
+                info!("Subscribed to cached heart rate characteristic of {}", name);
+                return Ok(characteristics);
+            }
+            Err(error) => info!(
+                "Failed to subscribe to cached characteristic of {}, rediscovering: {}",
+                name, error
+            ),
+        }
+    }
+    let characteristics = discover().await?;
+    subscribe_after(delay, name, || {
+        subscribe(characteristics.heart_rate.clone())
+    })
+    .await?;
+    info!("Subscribed to heart rate characteristic of {}", name);
+    Ok(characteristics)
+}
+
+/// Waits out `delay`, for straps that aren't ready right after discovery,
+/// then subscribes.
+async fn subscribe_after<F, Fut>(delay: Duration, name: &str, subscribe: F) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = btleplug::Result<()>>,
+{
+    if !delay.is_zero() {
+        info!("Waiting {:?} before subscribing to {}", delay, name);
+        time::sleep(delay).await;
+    }
+    Ok(subscribe().await?)
+}
+
+/// Runs an operation until it succeeds or has been retried `retries` times.
+async fn with_retries<T, F, Fut>(
+    retries: u32,
+    delay: Duration,
+    what: &str,
+    operation: F,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = btleplug::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => break Ok(value),
+            Err(error) if attempt < retries => {
+                attempt += 1;
+                info!(
+                    "Failed to {}, retrying ({}/{}): {}",
+                    what, attempt, retries, error
+                );
+                time::sleep(delay).await;
+            }
+            Err(error) => break Err(error.into()),
+        }
+    }
+}
+
+async fn discover_characteristics(
+    peripheral: &Peripheral,
+    name: &str,
+    options: &ConnectOptions,
+) -> Result<PeripheralCharacteristics> {
+    with_retries(
+        options.discovery_retries,
+        options.discovery_retry_delay,
+        "discover services",
+        || peripheral.discover_services(),
+    )
+    .await?;
+    let characteristics = peripheral.characteristics();
+
+    let heart_rate =
+        select_heart_rate_characteristic(&characteristics, options.heart_rate_characteristic);
+    let Some(heart_rate) = heart_rate.cloned() else {
+        let _ = peripheral.disconnect().await;
+        return Err(ConnectError::MissingHeartRate(String::from(name)).into());
+    };
+    let battery_level = select_battery_level_characteristic(
+        &characteristics,
+        options.battery_characteristic,
+        options.skip_battery,
+        name,
+    );
+
+    Ok(PeripheralCharacteristics {
+        battery_level,
+        heart_rate,
+    })
+}
+
+/// Picks the battery level characteristic, or none with --skip-battery so it
+/// is never read.
+fn select_battery_level_characteristic<'a>(
+    characteristics: impl IntoIterator<Item = &'a Characteristic>,
+    uuid: Uuid,
+    skip_battery: bool,
+    name: &str,
+) -> Option<Characteristic> {
+    // Streaming doesn't need the battery level.
+    if skip_battery {
+        return None;
+    }
+    let battery_level = characteristics
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .cloned();
+    if battery_level.is_none() {
+        info!("{} has no battery level characteristic", name);
+    }
+    battery_level
+}
+
+/// Picks the heart rate characteristic, preferring one that can notify or
+/// indicate when a peripheral exposes several.
+fn select_heart_rate_characteristic<'a>(
+    characteristics: impl IntoIterator<Item = &'a Characteristic>,
+    uuid: Uuid,
+) -> Option<&'a Characteristic> {
+    let candidates: Vec<_> = characteristics
+        .into_iter()
+        .filter(|characteristic| characteristic.uuid == uuid)
+        .collect();
+    if candidates.len() > 1 {
+        info!(
+            "Found {} heart rate characteristics, preferring one that notifies",
+            candidates.len()
+        );
+    }
+    let notifiable = CharPropFlags::NOTIFY | CharPropFlags::INDICATE;
+    candidates
+        .iter()
+        .find(|characteristic| characteristic.properties.intersects(notifiable))
+        .or(candidates.first())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::time::Instant;
+
+    #[test]
+    fn link_request_only_when_asked_and_supported() {
+        assert_eq!(link_request(Some(247), true), LinkRequest::Request(247));
+        assert_eq!(link_request(Some(247), false), LinkRequest::Unsupported);
+        assert_eq!(link_request(None::<u16>, true), LinkRequest::Skip);
+        assert_eq!(link_request(None::<u16>, false), LinkRequest::Skip);
+        let interval = Some(ConnectionInterval::Balanced);
+        assert_eq!(
+            link_request(interval, CONNECTION_PARAMETER_REQUESTS_SUPPORTED),
+            LinkRequest::Unsupported
+        );
+    }
+
+    fn properties(rssi: Option<i16>, heart_rate: bool) -> Option<PeripheralProperties> {
+        Some(PeripheralProperties {
+            rssi,
+            services: match heart_rate {
+                true => vec![HEART_RATE_SERVICE_UUID],
+                false => vec![],
+            },
+            ..PeripheralProperties::default()
+        })
+    }
+
+    #[test]
+    fn index_follows_the_menu_order() {
+        // In the order they were first seen in.
+        let candidates = vec![
+            ("far", properties(Some(-80), true)),
+            ("speaker", properties(Some(-40), false)),
+            ("silent", properties(None, true)),
+            ("near", properties(Some(-50), true)),
+            ("also near", properties(Some(-50), true)),
+        ];
+        let listed: Vec<_> = menu_order(candidates, false)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(listed, ["near", "also near", "far", "silent"]);
+        assert_eq!(select_by_index(listed.clone(), 0).unwrap(), "near");
+        assert_eq!(select_by_index(listed.clone(), 2).unwrap(), "far");
+        assert!(select_by_index(listed, 4).is_err());
+    }
+
+    #[test]
+    fn menu_lists_everything_without_heart_rate_peripherals() {
+        let candidates = vec![
+            ("speaker", properties(Some(-60), false)),
+            ("phone", properties(Some(-40), false)),
+        ];
+        let listed: Vec<_> = menu_order(candidates.clone(), false)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(listed, ["phone", "speaker"]);
+        assert_eq!(menu_order(candidates, true).len(), 2);
+    }
+
+    #[test]
+    fn connection_intervals_trade_latency_for_battery() {
+        let low_latency = ConnectionInterval::LowLatency.parameters();
+        assert_eq!(low_latency.min_interval, Duration::from_micros(7_500));
+        assert_eq!(low_latency.max_interval, Duration::from_millis(15));
+        assert_eq!(low_latency.latency, 0);
+        assert_eq!(low_latency.supervision_timeout, Duration::from_secs(2));
+
+        let balanced = ConnectionInterval::Balanced.parameters();
+        assert_eq!(balanced.min_interval, Duration::from_millis(30));
+        assert_eq!(balanced.max_interval, Duration::from_millis(50));
+        assert_eq!(balanced.latency, 0);
+        assert_eq!(balanced.supervision_timeout, Duration::from_secs(4));
+
+        let power_save = ConnectionInterval::PowerSave.parameters();
+        assert_eq!(power_save.min_interval, Duration::from_millis(100));
+        assert_eq!(power_save.max_interval, Duration::from_millis(125));
+        assert_eq!(power_save.latency, 4);
+        assert_eq!(power_save.supervision_timeout, Duration::from_secs(6));
+    }
+
+    #[test]
+    fn connection_intervals_are_within_the_specification() {
+        for interval in ConnectionInterval::value_variants() {
+            let parameters = interval.parameters();
+            assert!(parameters.min_interval >= Duration::from_micros(7_500));
+            assert!(parameters.min_interval <= parameters.max_interval);
+            assert!(parameters.max_interval <= Duration::from_secs(4));
+            // The supervision timeout must outlast the skipped connection events.
+            let skipped = parameters.max_interval * (u32::from(parameters.latency) + 1) * 2;
+            assert!(parameters.supervision_timeout > skipped);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribing_waits_for_the_delay() {
+        let delay = Duration::from_millis(50);
+        let calls = RefCell::new(Vec::new());
+        let start = Instant::now();
+        subscribe_after(delay, "Strap", || async {
+            calls.borrow_mut().push(("subscribe", start.elapsed()));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        let calls = calls.into_inner();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "subscribe");
+        assert!(calls[0].1 >= delay);
+    }
+
+    #[tokio::test]
+    async fn subscribe_errors_are_returned() {
+        let subscribed = subscribe_after(Duration::ZERO, "Strap", || async {
+            Err(btleplug::Error::NotConnected)
+        });
+        assert!(subscribed.await.is_err());
+    }
+
+    fn characteristics(service_uuid: Uuid) -> PeripheralCharacteristics {
+        PeripheralCharacteristics {
+            battery_level: None,
+            heart_rate: Characteristic {
+                uuid: HEART_RATE_CHARACTERISTIC_UUID,
+                service_uuid,
+                properties: CharPropFlags::NOTIFY,
+            },
+        }
+    }
+
+    /// Subscribes through a fake peripheral that rejects the stale handles
+    /// when `stale` is set, returning its calls in order.
+    async fn subscribe_calls(
+        cached: Option<PeripheralCharacteristics>,
+        stale: bool,
+    ) -> Vec<&'static str> {
+        let cached_service = cached.as_ref().map(|cached| cached.heart_rate.service_uuid);
+        let calls = RefCell::new(Vec::new());
+        let subscribed = subscribe_heart_rate(
+            cached.clone(),
+            Duration::ZERO,
+            "Strap",
+            |characteristic| {
+                let calls = &calls;
+                async move {
+                    if Some(characteristic.service_uuid) != cached_service {
+                        calls.borrow_mut().push("subscribe");
+                        return Ok(());
+                    }
+                    calls.borrow_mut().push("subscribe cached");
+                    match stale {
+                        true => Err(btleplug::Error::NotConnected),
+                        false => Ok(()),
+                    }
+                }
+            },
+            || async {
+                calls.borrow_mut().push("discover");
+                Ok(characteristics(HEART_RATE_SERVICE_UUID))
+            },
+        )
+        .await
+        .unwrap();
+        let expected = match (cached, stale) {
+            (Some(cached), false) => cached.heart_rate,
+            _ => characteristics(HEART_RATE_SERVICE_UUID).heart_rate,
+        };
+        assert_eq!(subscribed.heart_rate, expected);
+        calls.into_inner()
+    }
+
+    #[tokio::test]
+    async fn cached_characteristics_skip_discovery() {
+        let cached = Some(characteristics(Uuid::nil()));
+        assert_eq!(subscribe_calls(cached, false).await, ["subscribe cached"]);
+    }
+
+    #[tokio::test]
+    async fn stale_cached_characteristics_are_rediscovered() {
+        let cached = Some(characteristics(Uuid::nil()));
+        assert_eq!(
+            subscribe_calls(cached, true).await,
+            ["subscribe cached", "discover", "subscribe"]
+        );
+        assert_eq!(
+            subscribe_calls(None, false).await,
+            ["discover", "subscribe"]
+        );
+    }
+
+    fn address(last: u8) -> BDAddr {
+        BDAddr::from([0, 0, 0, 0, 0, last])
+    }
+
+    #[test]
+    fn redraws_only_when_the_list_changed() {
+        let listed = [
+            (address(1), Some(String::from("Strap"))),
+            (address(2), None),
+        ];
+        assert!(should_redraw(None, &listed, false));
+        assert!(!should_redraw(Some(&listed), &listed, false));
+        let reordered = [listed[1].clone(), listed[0].clone()];
+        assert!(!should_redraw(Some(&listed), &reordered, false));
+        let renamed = [listed[0].clone(), (address(2), Some(String::from("Band")))];
+        assert!(should_redraw(Some(&listed), &renamed, false));
+        assert!(should_redraw(Some(&listed), &listed[..1], false));
+    }
+
+    #[test]
+    fn redraws_an_unchanged_list_after_scan_again() {
+        let listed = [(address(1), Some(String::from("Strap")))];
+        assert!(should_redraw(Some(&listed), &listed, true));
+    }
+
+    #[test]
+    fn out_of_range_index_reports_the_peripheral_count() {
+        let error = select_by_index(vec!["Strap", "Band"], 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Peripheral index 2 is out of range, found 2 peripherals"
+        );
+        assert!(select_by_index(Vec::<&str>::new(), 0).is_err());
+    }
+
+    #[test]
+    fn prefers_the_heart_rate_characteristic_that_notifies() {
+        let characteristic = |uuid, service_uuid, properties| Characteristic {
+            uuid,
+            service_uuid,
+            properties,
+        };
+        let read = characteristic(
+            HEART_RATE_CHARACTERISTIC_UUID,
+            Uuid::nil(),
+            CharPropFlags::READ,
+        );
+        let notify = characteristic(
+            HEART_RATE_CHARACTERISTIC_UUID,
+            HEART_RATE_SERVICE_UUID,
+            CharPropFlags::NOTIFY,
+        );
+        let battery = characteristic(
+            BATTERY_LEVEL_CHARACTERISTIC_UUID,
+            Uuid::nil(),
+            CharPropFlags::NOTIFY,
+        );
+        let all = [battery.clone(), read.clone(), notify.clone()];
+        let uuid = HEART_RATE_CHARACTERISTIC_UUID;
+        assert_eq!(select_heart_rate_characteristic(&all, uuid), Some(&notify));
+        let without_notify = [battery.clone(), read.clone()];
+        assert_eq!(
+            select_heart_rate_characteristic(&without_notify, uuid),
+            Some(&read)
+        );
+        assert_eq!(select_heart_rate_characteristic(&[battery], uuid), None);
+    }
+
+    #[test]
+    fn skip_battery_never_picks_the_battery_level() {
+        let battery = Characteristic {
+            uuid: BATTERY_LEVEL_CHARACTERISTIC_UUID,
+            service_uuid: Uuid::nil(),
+            properties: CharPropFlags::READ,
+        };
+        let all = [
+            characteristics(HEART_RATE_SERVICE_UUID).heart_rate,
+            battery.clone(),
+        ];
+        let uuid = BATTERY_LEVEL_CHARACTERISTIC_UUID;
+        let picked = select_battery_level_characteristic(&all, uuid, false, "Strap");
+        assert_eq!(picked, Some(battery));
+        assert_eq!(
+            select_battery_level_characteristic(&all, uuid, true, "Strap"),
+            None
+        );
+    }
+
+    #[test]
+    fn scans_merge_in_first_seen_order() {
+        let listed = [address(1), address(2)];
+        let scanned = [address(3), address(1)];
+        let merged = merge_scan(&listed, &scanned, false);
+        assert_eq!(merged, [address(1), address(2), address(3)]);
+        let merged = merge_scan(&listed, &scanned, true);
+        assert_eq!(merged, [address(1), address(3)]);
+        let duplicated = [address(3), address(3)];
+        assert_eq!(merge_scan(&[], &duplicated, false), [address(3)]);
+    }
+
+    #[tokio::test]
+    async fn discovery_is_retried_after_an_error() {
+        let attempts = RefCell::new(0);
+        let discover = || async {
+            *attempts.borrow_mut() += 1;
+            match *attempts.borrow() {
+                1 => Err(btleplug::Error::NotConnected),
+                _ => Ok(()),
+            }
+        };
+        let discovered = with_retries(2, Duration::ZERO, "discover services", discover).await;
+        assert!(discovered.is_ok());
+        assert_eq!(attempts.into_inner(), 2);
+    }
+
+    #[tokio::test]
+    async fn discovery_gives_up_after_the_retries() {
+        let attempts = RefCell::new(0);
+        let discover = || async {
+            *attempts.borrow_mut() += 1;
+            Err::<(), _>(btleplug::Error::NotConnected)
+        };
+        let discovered = with_retries(2, Duration::ZERO, "discover services", discover).await;
+        assert!(discovered.is_err());
+        assert_eq!(attempts.into_inner(), 3);
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Holds sends back for a while after VRChat was first detected, so the
+/// avatar has time to load.
+pub struct LaunchGate {
+    delay: Duration,
+    detected_at: Arc<OnceLock<Instant>>,
+}
+
+impl LaunchGate {
+    pub fn new(delay: Duration, detected_at: Arc<OnceLock<Instant>>) -> Self {
+        Self { delay, detected_at }
+    }
+
+    /// Sends are allowed until VRChat is detected, as there might be no way
+    /// to detect it at all.
+    pub fn allows(&self, now: Instant) -> bool {
+        match self.detected_at.get() {
+            Some(detected_at) => now >= *detected_at + self.delay,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_sends_until_the_delay_after_detection() {
+        let presence = Arc::new(OnceLock::new());
+        let gate = LaunchGate::new(Duration::from_secs(10), presence.clone());
+        let start = Instant::now();
+        assert!(gate.allows(start));
+        presence.set(start).unwrap();
+        assert!(!gate.allows(start));
+        assert!(!gate.allows(start + Duration::from_secs(9)));
+        assert!(gate.allows(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn only_the_first_detection_counts() {
+        let start = Instant::now();
+        let presence = Arc::new(OnceLock::new());
+        let gate = LaunchGate::new(Duration::from_secs(10), presence.clone());
+        presence.set(start).unwrap();
+        assert!(presence.set(start + Duration::from_secs(60)).is_err());
+        assert!(gate.allows(start + Duration::from_secs(30)));
+    }
+}
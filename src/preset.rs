@@ -0,0 +1,226 @@
+use anyhow::{bail, Result};
+use rosc::OscType;
+
+/// How a heart rate parameter is derived and sent.
+#[derive(Clone, Copy, Debug)]
+pub enum ParameterValue {
+    /// Float mapping the normalized heart rate from [0.0, 1.0] to [low, high].
+    Normalized { low: f32, high: f32 },
+    /// Int of the heart rate, clamped to [min, max].
+    BeatsPerMinute { min: u8, max: u8 },
+    /// Int of a single decimal digit of the heart rate, 1 for ones, 10 for
+    /// tens and 100 for hundreds.
+    Digit { place: u8 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Parameter {
+    pub name: &'static str,
+    pub value: ParameterValue,
+}
+
+/// The heart rate parameters an avatar base expects.
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    /// `None` for the parameters under --osc-prefix.
+    pub prefix: Option<&'static str>,
+    pub parameters: &'static [Parameter],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "default",
+        prefix: None,
+        parameters: &[
+            Parameter {
+                name: "",
+                value: ParameterValue::Normalized {
+                    low: 0.0,
+                    high: 1.0,
+                },
+            },
+            Parameter {
+                name: "Int",
+                value: ParameterValue::BeatsPerMinute { min: 0, max: 255 },
+            },
+        ],
+    },
+    // https://github.com/200Tigersbloxed/HRtoVRChat_OSC
+    Preset {
+        name: "hrtovrchat",
+        prefix: Some("/avatar/parameters/"),
+        parameters: &[
+            Parameter {
+                name: "HR",
+                value: ParameterValue::BeatsPerMinute { min: 0, max: 255 },
+            },
+            Parameter {
+                name: "onesHR",
+                value: ParameterValue::Digit { place: 1 },
+            },
+            Parameter {
+                name: "tensHR",
+                value: ParameterValue::Digit { place: 10 },
+            },
+            Parameter {
+                name: "hundredsHR",
+                value: ParameterValue::Digit { place: 100 },
+            },
+            Parameter {
+                name: "floatHR",
+                value: ParameterValue::Normalized {
+                    low: -1.0,
+                    high: 1.0,
+                },
+            },
+        ],
+    },
+    // https://github.com/VolcanicArts/VRCOSC
+    Preset {
+        name: "vrcosc",
+        prefix: Some("/avatar/parameters/VRCOSC/Heartrate/"),
+        parameters: &[
+            Parameter {
+                name: "Normalised",
+                value: ParameterValue::Normalized {
+                    low: 0.0,
+                    high: 1.0,
+                },
+            },
+            Parameter {
+                name: "Units",
+                value: ParameterValue::Digit { place: 1 },
+            },
+            Parameter {
+                name: "Tens",
+                value: ParameterValue::Digit { place: 10 },
+            },
+            Parameter {
+                name: "Hundreds",
+                value: ParameterValue::Digit { place: 100 },
+            },
+        ],
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static Preset> {
+    match PRESETS.iter().find(|preset| preset.name == name) {
+        Some(preset) => Ok(preset),
+        None => {
+            let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+            bail!(
+                "Unknown preset {:?}, available presets are {}",
+                name,
+                names.join(", ")
+            );
+        }
+    }
+}
+
+/// The parameters of a preset, under its own or an overridden prefix.
+pub struct HeartRateParameters {
+    prefix: String,
+    parameters: &'static [Parameter],
+}
+
+impl HeartRateParameters {
+    pub fn new(preset: &Preset, prefix: Option<&str>, osc_prefix: &str) -> Self {
+        Self {
+            prefix: String::from(prefix.or(preset.prefix).unwrap_or(osc_prefix)),
+            parameters: preset.parameters,
+        }
+    }
+
+    fn address(&self, parameter: &Parameter) -> String {
+        format!("{}{}", self.prefix, parameter.name)
+    }
+
+    /// Messages for the parameters derived from the normalized heart rate,
+    /// sent on every update of it, eased or not.
+    pub fn normalized_messages(&self, normalized: f32) -> Vec<(String, OscType)> {
+        self.parameters
+            .iter()
+            .filter_map(|parameter| match parameter.value {
+                ParameterValue::Normalized { low, high } => Some((
+                    self.address(parameter),
+                    OscType::Float(low + (high - low) * normalized.clamp(0.0, 1.0)),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The addresses of the normalized parameters that stay in [0.0, 1.0].
+    pub fn unit_range_addresses(&self) -> Vec<String> {
+        self.parameters
+            .iter()
+            .filter(|parameter| match parameter.value {
+                ParameterValue::Normalized { low, high } => low >= 0.0 && high <= 1.0,
+                _ => false,
+            })
+            .map(|parameter| self.address(parameter))
+            .collect()
+    }
+
+    /// Messages for the parameters derived from the heart rate itself.
+    pub fn beats_per_minute_messages(&self, beats_per_minute: u8) -> Vec<(String, OscType)> {
+        self.parameters
+            .iter()
+            .filter_map(|parameter| {
+                let value = match parameter.value {
+                    ParameterValue::Normalized { .. } => return None,
+                    ParameterValue::BeatsPerMinute { min, max } => beats_per_minute.clamp(min, max),
+                    ParameterValue::Digit { place } => beats_per_minute / place % 10,
+                };
+                Some((self.address(parameter), OscType::Int(i32::from(value))))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_applies_its_prefix_names_and_ranges() {
+        let preset = find("hrtovrchat").unwrap();
+        let parameters = HeartRateParameters::new(preset, None, "/avatar/parameters/HeartRate");
+        let expected = [
+            (String::from("/avatar/parameters/HR"), OscType::Int(142)),
+            (String::from("/avatar/parameters/onesHR"), OscType::Int(2)),
+            (String::from("/avatar/parameters/tensHR"), OscType::Int(4)),
+            (
+                String::from("/avatar/parameters/hundredsHR"),
+                OscType::Int(1),
+            ),
+        ];
+        assert_eq!(parameters.beats_per_minute_messages(142), expected);
+        let expected = [(
+            String::from("/avatar/parameters/floatHR"),
+            OscType::Float(0.5),
+        )];
+        assert_eq!(parameters.normalized_messages(0.75), expected);
+        assert!(parameters.unit_range_addresses().is_empty());
+    }
+
+    #[test]
+    fn default_preset_follows_the_osc_prefix() {
+        let preset = find("default").unwrap();
+        let parameters = HeartRateParameters::new(preset, None, "/hr/");
+        let expected = [(String::from("/hr/"), OscType::Float(1.0))];
+        assert_eq!(parameters.normalized_messages(1.4), expected);
+        let overridden = HeartRateParameters::new(preset, Some("/other/"), "/hr/");
+        assert_eq!(overridden.unit_range_addresses(), ["/other/"]);
+    }
+
+    #[test]
+    fn unknown_presets_list_the_available_ones() {
+        let error = find("unknown").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Unknown preset \"unknown\", available presets are default, hrtovrchat, vrcosc"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cancelled by user")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Turns the result of an `interact_opt` prompt into a selection, telling
+/// the user cancelling it apart from genuine I/O errors.
+pub fn selection(result: io::Result<Option<usize>>) -> Result<usize> {
+    match result {
+        Ok(Some(selection)) => Ok(selection),
+        Ok(None) => Err(Cancelled.into()),
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => Err(Cancelled.into()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_cancelled(result: io::Result<Option<usize>>) -> bool {
+        selection(result).unwrap_err().is::<Cancelled>()
+    }
+
+    #[test]
+    fn cancellation_is_told_apart_from_io_errors() {
+        assert_eq!(selection(Ok(Some(2))).unwrap(), 2);
+        assert!(is_cancelled(Ok(None)));
+        assert!(is_cancelled(Err(io::ErrorKind::Interrupted.into())));
+        assert!(!is_cancelled(Err(io::ErrorKind::BrokenPipe.into())));
+    }
+}
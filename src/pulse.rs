@@ -0,0 +1,26 @@
+/// A trigger that is true for a single update and false again after it.
+#[derive(Default)]
+pub struct Pulse {
+    pending: bool,
+    active: bool,
+}
+
+impl Pulse {
+    pub fn trigger(&mut self) {
+        self.pending = true;
+    }
+
+    /// Returns the value to send for this update, if it changed.
+    pub fn update(&mut self) -> Option<bool> {
+        if self.pending {
+            self.pending = false;
+            self.active = true;
+            Some(true)
+        } else if self.active {
+            self.active = false;
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
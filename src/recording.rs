@@ -0,0 +1,215 @@
+use crate::device::DeviceEvent;
+use crate::energy::EnergyAccumulator;
+use crate::measurement::{self, Notification};
+use crate::sample::Sample;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::info;
+
+const DISTRIBUTION_BUCKET_SIZE: u8 = 10;
+
+pub struct Recording {
+    pub duration: Duration,
+    pub samples: Vec<Sample>,
+    pub rejected: usize,
+}
+
+/// Records every notification for `duration`, starting once a peripheral is connected.
+pub async fn record(
+    events: &mut mpsc::Receiver<DeviceEvent>,
+    duration: Duration,
+) -> Result<Recording> {
+    let mut recording = Recording {
+        duration,
+        samples: vec![],
+        rejected: 0,
+    };
+    let mut deadline = None;
+    let mut energy = EnergyAccumulator::new();
+
+    loop {
+        let event = match deadline {
+            Some(deadline) => match time::timeout_at(deadline, events.recv()).await {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+            None => events.recv().await,
+        };
+        match event {
+            Some(DeviceEvent::Connected { .. }) => {
+                if deadline.is_none() {
+                    info!("Recording for {:?}", duration);
+                    deadline = Some(time::Instant::now() + duration);
+                }
+            }
+            Some(DeviceEvent::Notification {
+                device,
+                notification,
+                ..
+            }) => {
+                let measurement =
+                    match measurement::parse_notification(notification.uuid, &notification.value) {
+                        Notification::HeartRate(Some(measurement)) => measurement,
+                        Notification::HeartRate(None) => {
+                            recording.rejected += 1;
+                            continue;
+                        }
+                        Notification::Unknown(_) => continue,
+                    };
+                let energy_expended = measurement
+                    .energy_expended
+                    .map(|energy_expended| energy.update(energy_expended));
+                recording.samples.push(Sample {
+                    energy_expended,
+                    ..Sample::new(
+                        chrono::Local::now().to_rfc3339(),
+                        measurement.beats_per_minute(),
+                        &measurement,
+                        device + 1,
+                    )
+                });
+            }
+            Some(
+                DeviceEvent::Disconnected { .. }
+                | DeviceEvent::Battery { .. }
+                | DeviceEvent::Information { .. }
+                | DeviceEvent::EnergyResetFailed { .. }
+                | DeviceEvent::ReconnectLimitReached { .. },
+            ) => {}
+            Some(DeviceEvent::Failed { error, .. }) => return Err(error),
+            Some(DeviceEvent::Finished { .. }) | None => break,
+        }
+    }
+
+    Ok(recording)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecordingStats {
+    pub samples: usize,
+    pub sample_rate: f64,
+    pub min_heart_rate: Option<u8>,
+    pub max_heart_rate: Option<u8>,
+    pub mean_heart_rate: Option<f64>,
+    pub distribution: BTreeMap<u8, usize>,
+    pub rr_interval_samples: usize,
+    pub dropped: u64,
+    pub rejected: usize,
+}
+
+impl RecordingStats {
+    pub fn compute(recording: &Recording, dropped: u64) -> Self {
+        let heart_rates = recording.samples.iter().map(|sample| sample.heart_rate);
+        let samples = recording.samples.len();
+        let seconds = recording.duration.as_secs_f64();
+
+        let mut distribution = BTreeMap::new();
+        for heart_rate in heart_rates.clone() {
+            let bucket = heart_rate - heart_rate % DISTRIBUTION_BUCKET_SIZE;
+            *distribution.entry(bucket).or_insert(0) += 1;
+        }
+
+        Self {
+            samples,
+            sample_rate: if seconds > 0.0 {
+                samples as f64 / seconds
+            } else {
+                0.0
+            },
+            min_heart_rate: heart_rates.clone().min(),
+            max_heart_rate: heart_rates.clone().max(),
+            mean_heart_rate: (samples > 0)
+                .then(|| heart_rates.map(f64::from).sum::<f64>() / samples as f64),
+            distribution,
+            rr_interval_samples: recording
+                .samples
+                .iter()
+                .filter(|sample| !sample.rr_intervals.is_empty())
+                .count(),
+            dropped,
+            rejected: recording.rejected,
+        }
+    }
+}
+
+impl fmt::Display for RecordingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Samples:     {}", self.samples)?;
+        writeln!(f, "Sample rate: {:.2}/s", self.sample_rate)?;
+        match (
+            self.min_heart_rate,
+            self.max_heart_rate,
+            self.mean_heart_rate,
+        ) {
+            (Some(min), Some(max), Some(mean)) => {
+                writeln!(f, "Heart rate:  {} min, {:.1} mean, {} max", min, mean, max)?
+            }
+            _ => writeln!(f, "Heart rate:  -")?,
+        }
+        writeln!(f, "With RR:     {}", self.rr_interval_samples)?;
+        writeln!(f, "Dropped:     {}", self.dropped)?;
+        writeln!(f, "Rejected:    {}", self.rejected)?;
+        writeln!(f, "Distribution:")?;
+        for (bucket, count) in &self.distribution {
+            writeln!(
+                f,
+                "  {:>3}-{:<3} {:>5} {}",
+                bucket,
+                bucket.saturating_add(DISTRIBUTION_BUCKET_SIZE - 1),
+                count,
+                "#".repeat(count * 40 / self.samples.max(1))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::parse_heart_rate_measurement;
+
+    fn sample(value: &[u8]) -> Sample {
+        let measurement = parse_heart_rate_measurement(value).unwrap();
+        let heart_rate = measurement.beats_per_minute();
+        Sample::new(String::new(), heart_rate, &measurement, 1)
+    }
+
+    #[test]
+    fn stats_use_the_parsed_measurements() {
+        let recording = Recording {
+            duration: Duration::from_secs(2),
+            samples: vec![sample(&[0x10, 65, 0x00, 0x04]), sample(&[0x01, 0x4b, 0x00])],
+            rejected: 1,
+        };
+        let stats = RecordingStats::compute(&recording, 0);
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.sample_rate, 1.0);
+        assert_eq!(stats.min_heart_rate, Some(65));
+        assert_eq!(stats.max_heart_rate, Some(75));
+        assert_eq!(stats.mean_heart_rate, Some(70.0));
+        assert_eq!(stats.distribution, BTreeMap::from([(60, 1), (70, 1)]));
+        assert_eq!(stats.rr_interval_samples, 1);
+        assert_eq!(stats.rejected, 1);
+    }
+
+    #[test]
+    fn stats_of_an_empty_recording() {
+        let recording = Recording {
+            duration: Duration::ZERO,
+            samples: vec![],
+            rejected: 0,
+        };
+        let stats = RecordingStats::compute(&recording, 3);
+        assert_eq!(stats.samples, 0);
+        assert_eq!(stats.sample_rate, 0.0);
+        assert_eq!(stats.mean_heart_rate, None);
+        assert!(stats.distribution.is_empty());
+        assert_eq!(stats.dropped, 3);
+        assert!(stats.to_string().contains("Heart rate:  -"));
+    }
+}
@@ -0,0 +1,25 @@
+use crate::config;
+use anyhow::{anyhow, Result};
+use btleplug::api::BDAddr;
+use std::fs;
+use std::path::PathBuf;
+
+/// `last_device` in the configuration directory of the platform.
+fn path() -> Option<PathBuf> {
+    Some(config::directory()?.join("last_device"))
+}
+
+/// The address of the peripheral connected to last, if there is one.
+pub fn load() -> Option<BDAddr> {
+    let contents = fs::read_to_string(path()?).ok()?;
+    BDAddr::from_str_delim(contents.trim()).ok()
+}
+
+pub fn save(address: BDAddr) -> Result<()> {
+    let path = path().ok_or_else(|| anyhow!("No configuration directory to remember in"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", address))?;
+    Ok(())
+}
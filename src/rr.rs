@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Tracks whether the connected peripheral reports RR intervals at all, so
+/// outputs depending on them can fall back to approximations from the heart
+/// rate, or stop where there is none.
+pub struct RrAvailability {
+    warmup: Duration,
+    dependents: Vec<&'static str>,
+    withheld: Vec<&'static str>,
+    connected_at: Instant,
+    available: Option<bool>,
+}
+
+impl RrAvailability {
+    pub fn new(
+        warmup: Duration,
+        dependents: Vec<&'static str>,
+        withheld: Vec<&'static str>,
+        now: Instant,
+    ) -> Self {
+        Self {
+            warmup,
+            dependents,
+            withheld,
+            connected_at: now,
+            available: None,
+        }
+    }
+
+    /// Starts detecting from scratch, as a different peripheral might be connected.
+    pub fn reset(&mut self, now: Instant) {
+        self.connected_at = now;
+        self.available = None;
+    }
+
+    /// Whether RR intervals can be relied on, assumed until the warmup shows
+    /// the peripheral doesn't report them.
+    pub fn available(&self) -> bool {
+        self.available != Some(false)
+    }
+
+    /// Returns whether the peripheral was just found not to report RR
+    /// intervals, which is warned about once per connection.
+    pub fn observe(&mut self, has_rr_intervals: bool, now: Instant) -> bool {
+        if self.available == Some(true) {
+            return false;
+        }
+        if has_rr_intervals {
+            self.available = Some(true);
+            return false;
+        }
+        if self.available.is_some() || now.duration_since(self.connected_at) < self.warmup {
+            return false;
+        }
+
+        self.available = Some(false);
+        let mut fallbacks = vec![];
+        if !self.dependents.is_empty() {
+            let approximated = self.dependents.join(", ");
+            fallbacks.push(format!(
+                "approximating {} from the heart rate",
+                approximated
+            ));
+        }
+        if !self.withheld.is_empty() {
+            fallbacks.push(format!("not sending {}", self.withheld.join(", ")));
+        }
+        if fallbacks.is_empty() {
+            info!("Peripheral doesn't report RR intervals");
+        } else {
+            warn!(
+                "Peripheral doesn't report RR intervals, {}",
+                fallbacks.join(" and ")
+            );
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARMUP: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn missing_rr_intervals_fall_back_once_after_warmup() {
+        let start = Instant::now();
+        let mut availability = RrAvailability::new(WARMUP, vec!["HeartBeat"], vec![], start);
+        assert!(!availability.observe(false, start + Duration::from_secs(10)));
+        assert!(availability.available());
+
+        let warnings = (30..40)
+            .filter(|seconds| availability.observe(false, start + Duration::from_secs(*seconds)))
+            .count();
+        assert_eq!(warnings, 1);
+        assert!(!availability.available());
+    }
+
+    #[test]
+    fn reported_rr_intervals_stay_available() {
+        let start = Instant::now();
+        let mut availability = RrAvailability::new(WARMUP, vec!["HeartBeat"], vec![], start);
+        assert!(!availability.observe(true, start));
+        assert!(!availability.observe(false, start + Duration::from_secs(60)));
+        assert!(availability.available());
+    }
+
+    #[test]
+    fn reset_detects_again() {
+        let start = Instant::now();
+        let mut availability = RrAvailability::new(WARMUP, vec!["HeartBeat"], vec![], start);
+        assert!(availability.observe(false, start + WARMUP));
+        let reconnected = start + Duration::from_secs(60);
+        availability.reset(reconnected);
+        assert!(availability.available());
+        assert!(availability.observe(false, reconnected + WARMUP));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::measurement::HeartRateMeasurement;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Sample {
+    pub timestamp: String,
+    pub heart_rate: u8,
+    /// In milliseconds, empty when the sensor doesn't report them.
+    pub rr_intervals: Vec<u16>,
+    pub contact: Option<bool>,
+    /// The position of the --peripheral-address it came from, 1 for the first
+    /// and 2 for the backup.
+    pub device: usize,
+    /// In kilojoules since the first measurement reporting it, counted
+    /// across the wraparounds of the peripheral's 16-bit counter.
+    pub energy_expended: Option<u64>,
+    /// The RMSSD of the RR intervals in milliseconds, with --hrv-window.
+    pub hrv: Option<f32>,
+}
+
+impl Sample {
+    /// A sample of the measurement, whose heart rate may be merged from
+    /// several peripherals. The energy expended is left to the caller, which
+    /// accumulates it.
+    pub fn new(
+        timestamp: String,
+        heart_rate: u8,
+        measurement: &HeartRateMeasurement,
+        device: usize,
+    ) -> Self {
+        Self {
+            timestamp,
+            heart_rate,
+            rr_intervals: measurement.rr_intervals_ms(),
+            contact: measurement.sensor_contact,
+            device,
+            energy_expended: None,
+            hrv: None,
+        }
+    }
+
+    /// The sample and battery level as a single line of JSON.
+    pub fn to_json(&self, battery_level: Option<u8>) -> String {
+        json!({
+            "timestamp": self.timestamp,
+            "heart_rate": self.heart_rate,
+            "battery_level": battery_level,
+            "rr_intervals": self.rr_intervals,
+            "contact": self.contact,
+            "device": self.device,
+            "energy_expended": self.energy_expended,
+            "hrv": self.hrv,
+        })
+        .to_string()
+    }
+
+    /// The RR intervals joined by semicolons, for a single CSV column.
+    pub fn rr_intervals_column(&self) -> String {
+        let rr_intervals: Vec<_> = self.rr_intervals.iter().map(u16::to_string).collect();
+        rr_intervals.join(";")
+    }
+
+    /// 1 or 0, or empty when the sensor doesn't report contact.
+    pub fn contact_column(&self) -> &'static str {
+        match self.contact {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "",
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+const LOW: f32 = 60.0;
+const HIGH: f32 = 160.0;
+const PERIOD: Duration = Duration::from_secs(120);
+/// Largest deviation of the noise from the sine, in bpm.
+const NOISE: f32 = 4.0;
+
+/// A heart rate walking between 60 and 160 bpm, as a slow sine with noise
+/// on top, or a fixed one.
+pub struct SimulatedHeartRate {
+    fixed: Option<u8>,
+    state: u32,
+}
+
+impl SimulatedHeartRate {
+    pub fn new(fixed: Option<u8>) -> Self {
+        Self {
+            fixed,
+            state: 0x9e37_79b9,
+        }
+    }
+
+    /// The heart rate `elapsed` into the simulation.
+    pub fn next(&mut self, elapsed: Duration) -> u8 {
+        if let Some(fixed) = self.fixed {
+            return fixed;
+        }
+        let phase = elapsed.as_secs_f32() / PERIOD.as_secs_f32() * TAU;
+        let middle = (LOW + HIGH) / 2.0;
+        let amplitude = (HIGH - LOW) / 2.0 - NOISE;
+        let heart_rate = middle + amplitude * phase.sin() + NOISE * self.noise();
+        heart_rate.round().clamp(LOW, HIGH) as u8
+    }
+
+    /// Uniform noise in [-1.0, 1.0] from a xorshift generator, so runs are
+    /// repeatable.
+    fn noise(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// A Heart Rate Measurement value with a UINT8 heart rate and nothing else.
+pub fn measurement(heart_rate: u8) -> Vec<u8> {
+    vec![0x00, heart_rate]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::parse_heart_rate_measurement;
+
+    #[test]
+    fn pinned_heart_rate_is_deterministic() {
+        let mut simulated = SimulatedHeartRate::new(Some(95));
+        for seconds in 0..300 {
+            assert_eq!(simulated.next(Duration::from_secs(seconds)), 95);
+        }
+    }
+
+    #[test]
+    fn walk_stays_in_range_and_repeats() {
+        let walk = |simulated: &mut SimulatedHeartRate| -> Vec<u8> {
+            (0..600)
+                .map(|tenths| simulated.next(Duration::from_millis(tenths * 100)))
+                .collect()
+        };
+        let first = walk(&mut SimulatedHeartRate::new(None));
+        assert!(first
+            .iter()
+            .all(|heart_rate| (60..=160).contains(heart_rate)));
+        assert_eq!(first, walk(&mut SimulatedHeartRate::new(None)));
+    }
+
+    #[test]
+    fn measurements_parse_back() {
+        let measurement = parse_heart_rate_measurement(&measurement(128)).unwrap();
+        assert_eq!(measurement.beats_per_minute(), 128);
+        assert!(measurement.rr_intervals.is_empty());
+    }
+}
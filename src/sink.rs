@@ -0,0 +1,253 @@
+use crate::sample::Sample;
+use crate::telemetry;
+use anyhow::Result;
+use clap::ValueEnum;
+use csv::{QuoteStyle, Writer, WriterBuilder};
+use rosc::OscType;
+use serde::Serialize;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::Path;
+
+pub const CSV_HEADER: [&str; 7] = [
+    "timestamp",
+    "heart_rate",
+    "rr_intervals",
+    "contact",
+    "device",
+    "energy_expended",
+    "hrv",
+];
+
+pub fn csv_writer<W: Write>(writer: W, delimiter: u8, quote_style: QuoteStyle) -> Writer<W> {
+    WriterBuilder::new()
+        .delimiter(delimiter)
+        .quote_style(quote_style)
+        .from_writer(writer)
+}
+
+/// A file format for the session log.
+#[derive(ValueEnum, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    Csv,
+    /// A JSON object per line
+    Jsonl,
+}
+
+impl LogFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// A destination for logged samples.
+pub trait LogSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// The paused state requested by a message to the pause address: a bool or
+/// int argument sets it, no argument toggles it.
+pub fn requested_pause(arguments: &[OscType], paused: bool) -> bool {
+    match arguments.first() {
+        Some(OscType::Bool(pause)) => *pause,
+        Some(OscType::Int(pause)) => *pause != 0,
+        _ => !paused,
+    }
+}
+
+pub struct CsvSink {
+    writer: Writer<File>,
+}
+
+impl CsvSink {
+    pub fn new(writer: Writer<File>) -> Self {
+        Self { writer }
+    }
+}
+
+impl LogSink for CsvSink {
+    fn write(&mut self, sample: &Sample, _battery_level: Option<u8>) -> Result<()> {
+        self.writer.write_record(telemetry::csv_record(sample))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct JsonlSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonlSink {
+    pub fn open(path: impl AsRef<Path>, append: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl LogSink for JsonlSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()> {
+        writeln!(self.writer, "{}", sample.to_json(battery_level))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Prints a compact JSON object per sample to stdout, for piping into other
+/// tools while the logs go to stderr.
+pub struct StdoutSink {
+    stdout: Stdout,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl LogSink for StdoutSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()> {
+        let line = json!({
+            "ts": sample.timestamp,
+            "bpm": sample.heart_rate,
+            "rr": sample.rr_intervals,
+            "battery": battery_level,
+        });
+        let mut stdout = self.stdout.lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn write(records: &[&[&str]], delimiter: u8, quote_style: QuoteStyle) -> String {
+        let mut writer = csv_writer(vec![], delimiter, quote_style);
+        for record in records {
+            writer.write_record(*record).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_uses_configured_delimiter() {
+        let written = write(
+            &[&["timestamp", "heart_rate"], &["t", "72"]],
+            b';',
+            QuoteStyle::Necessary,
+        );
+        assert_eq!(written, "timestamp;heart_rate\nt;72\n");
+    }
+
+    #[test]
+    fn csv_quotes_fields_containing_delimiter() {
+        let written = write(&[&["a;b", "72"]], b';', QuoteStyle::Necessary);
+        assert_eq!(written, "\"a;b\";72\n");
+    }
+
+    #[test]
+    fn rr_intervals_are_quoted_with_a_semicolon_delimiter() {
+        let sample = Sample {
+            timestamp: String::from("2026-01-01T00:00:00+00:00"),
+            heart_rate: 72,
+            rr_intervals: vec![1000, 950],
+            contact: Some(true),
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        };
+        let record = telemetry::csv_record(&sample);
+        let record: Vec<_> = record.iter().map(String::as_str).collect();
+        let written = write(&[&record], b';', QuoteStyle::Necessary);
+        assert!(written.contains(";\"1000;950\";"));
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .has_headers(false)
+            .from_reader(written.as_bytes());
+        let read = reader.records().next().unwrap().unwrap();
+        assert_eq!(read.len(), CSV_HEADER.len());
+        assert_eq!(&read[2], "1000;950");
+    }
+
+    #[test]
+    fn pause_messages_set_or_toggle_the_state() {
+        assert!(requested_pause(&[], false));
+        assert!(!requested_pause(&[], true));
+        assert!(requested_pause(&[OscType::Bool(true)], true));
+        assert!(!requested_pause(&[OscType::Bool(false)], false));
+        assert!(requested_pause(&[OscType::Int(1)], false));
+        assert!(!requested_pause(&[OscType::Int(0)], true));
+        let paused = (0..3).fold(false, |paused, _| requested_pause(&[], paused));
+        assert!(paused);
+    }
+
+    #[test]
+    fn samples_fan_out_to_csv_and_jsonl() {
+        let directory = env::temp_dir();
+        let name = format!("vrc_sa_node-sinks-{}", process::id());
+        let csv_path = directory.join(format!("{}.csv", name));
+        let jsonl_path = directory.join(format!("{}.jsonl", name));
+        let csv_file = File::create(&csv_path).unwrap();
+        let mut sinks: Vec<Box<dyn LogSink>> = vec![
+            Box::new(CsvSink::new(csv_writer(
+                csv_file,
+                b',',
+                QuoteStyle::Necessary,
+            ))),
+            Box::new(JsonlSink::open(&jsonl_path, false).unwrap()),
+        ];
+        let sample = Sample {
+            timestamp: String::from("2026-01-01T00:00:00+00:00"),
+            heart_rate: 72,
+            rr_intervals: vec![830, 845],
+            contact: Some(true),
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        };
+        for sink in &mut sinks {
+            sink.write(&sample, Some(85)).unwrap();
+        }
+        drop(sinks);
+
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let jsonl = fs::read_to_string(&jsonl_path).unwrap();
+        fs::remove_file(&csv_path).unwrap();
+        fs::remove_file(&jsonl_path).unwrap();
+        assert_eq!(csv, "2026-01-01T00:00:00+00:00,72,830;845,1,1,,\n");
+        assert_eq!(jsonl, format!("{}\n", sample.to_json(Some(85))));
+    }
+}
@@ -0,0 +1,29 @@
+/// An exponential moving average of the heart rate, weighting the previous
+/// average by `alpha` and the new heart rate by the rest.
+pub struct ExponentialMovingAverage {
+    alpha: f32,
+    average: Option<f32>,
+}
+
+impl ExponentialMovingAverage {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            average: None,
+        }
+    }
+
+    /// Starts over from the next heart rate.
+    pub fn reset(&mut self) {
+        self.average = None;
+    }
+
+    pub fn update(&mut self, heart_rate: f32) -> f32 {
+        let average = match self.average {
+            Some(average) => self.alpha * average + (1.0 - self.alpha) * heart_rate,
+            None => heart_rate,
+        };
+        self.average = Some(average);
+        average
+    }
+}
@@ -0,0 +1,57 @@
+/// Weight of each new sample in the baseline.
+const BASELINE_SMOOTHING: f32 = 0.1;
+
+/// Detects sharp rises of the heart rate above a smoothed baseline.
+pub struct SpikeDetector {
+    delta: f32,
+    baseline: Option<f32>,
+    above: bool,
+}
+
+impl SpikeDetector {
+    pub fn new(delta: f32) -> Self {
+        Self {
+            delta,
+            baseline: None,
+            above: false,
+        }
+    }
+
+    /// Returns whether this sample starts a spike. A spike only triggers again
+    /// once the heart rate has fallen back to within the delta.
+    pub fn update(&mut self, beats_per_minute: u8) -> bool {
+        let value = f32::from(beats_per_minute);
+        let baseline = *self.baseline.get_or_insert(value);
+        let above = value - baseline > self.delta;
+        let spike = above && !self.above;
+        self.above = above;
+        self.baseline = Some(baseline + (value - baseline) * BASELINE_SMOOTHING);
+        spike
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spikes(delta: f32, heart_rates: impl IntoIterator<Item = u8>) -> Vec<usize> {
+        let mut detector = SpikeDetector::new(delta);
+        let spikes = heart_rates
+            .into_iter()
+            .enumerate()
+            .filter(|(_, heart_rate)| detector.update(*heart_rate));
+        spikes.map(|(index, _)| index).collect()
+    }
+
+    #[test]
+    fn sharp_rises_trigger_once() {
+        let heart_rates = [70, 70, 70, 100, 100, 100, 70, 70, 100];
+        assert_eq!(spikes(20.0, heart_rates), [3, 8]);
+    }
+
+    #[test]
+    fn gradual_rises_do_not_trigger() {
+        assert!(spikes(20.0, 70..150).is_empty());
+        assert!(spikes(20.0, [70, 85, 70, 89, 70]).is_empty());
+    }
+}
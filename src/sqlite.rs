@@ -0,0 +1,153 @@
+use crate::sample::Sample;
+use crate::sink::LogSink;
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const BATCH_SIZE: usize = 10;
+
+struct Row {
+    timestamp: String,
+    bpm: u8,
+    rr: Option<String>,
+    battery: Option<u8>,
+}
+
+/// Logs samples to a `samples` table, inserting them in batches.
+pub struct SqliteSink {
+    connection: Connection,
+    session_id: String,
+    pending: Vec<Row>,
+}
+
+impl SqliteSink {
+    pub fn open(path: impl AsRef<Path>, session_id: String) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS samples (
+                session_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                bpm INTEGER NOT NULL,
+                rr TEXT,
+                battery INTEGER
+            )",
+            [],
+        )?;
+        Ok(Self {
+            connection,
+            session_id,
+            pending: vec![],
+        })
+    }
+}
+
+impl LogSink for SqliteSink {
+    fn write(&mut self, sample: &Sample, battery_level: Option<u8>) -> Result<()> {
+        self.pending.push(Row {
+            timestamp: sample.timestamp.clone(),
+            bpm: sample.heart_rate,
+            rr: (!sample.rr_intervals.is_empty()).then(|| sample.rr_intervals_column()),
+            battery: battery_level,
+        });
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT INTO samples (session_id, timestamp, bpm, rr, battery)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for row in self.pending.drain(..) {
+                statement.execute(params![
+                    self.session_id,
+                    row.timestamp,
+                    row.bpm,
+                    row.rr,
+                    row.battery
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: &str, heart_rate: u8, rr_intervals: Vec<u16>) -> Sample {
+        Sample {
+            timestamp: String::from(timestamp),
+            heart_rate,
+            rr_intervals,
+            contact: None,
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        }
+    }
+
+    /// Session ID, timestamp, bpm, RR intervals and battery level.
+    type StoredRow = (String, String, u8, Option<String>, Option<u8>);
+
+    fn rows(sink: &SqliteSink) -> Vec<StoredRow> {
+        let mut statement = sink
+            .connection
+            .prepare("SELECT session_id, timestamp, bpm, rr, battery FROM samples")
+            .unwrap();
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        });
+        rows.unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn samples_are_read_back() {
+        let mut sink = SqliteSink::open(":memory:", String::from("session")).unwrap();
+        sink.write(&sample("t1", 72, vec![800, 810]), Some(90))
+            .unwrap();
+        sink.write(&sample("t2", 75, vec![]), None).unwrap();
+        assert!(rows(&sink).is_empty());
+        sink.flush().unwrap();
+        let session = String::from("session");
+        let expected = [
+            (
+                session.clone(),
+                String::from("t1"),
+                72,
+                Some(String::from("800;810")),
+                Some(90),
+            ),
+            (session, String::from("t2"), 75, None, None),
+        ];
+        assert_eq!(rows(&sink), expected);
+    }
+
+    #[test]
+    fn full_batches_are_inserted_right_away() {
+        let mut sink = SqliteSink::open(":memory:", String::from("session")).unwrap();
+        for _ in 0..BATCH_SIZE {
+            sink.write(&sample("t", 72, vec![]), None).unwrap();
+        }
+        assert_eq!(rows(&sink).len(), BATCH_SIZE);
+        assert!(sink.pending.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::sample::Sample;
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::info;
+
+const RESPONSE_HEADER: &str = "HTTP/1.1 200 OK\r\n\
+    Content-Type: text/event-stream\r\n\
+    Cache-Control: no-cache\r\n\
+    Connection: keep-alive\r\n\
+    Access-Control-Allow-Origin: *\r\n\r\n";
+
+/// Serves every sample sent on the returned channel as a server-sent event.
+pub async fn spawn(port: u16) -> Result<broadcast::Sender<Sample>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Serving server-sent events on port {}", port);
+
+    let (sender, _) = broadcast::channel(16);
+    let client_sender = sender.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    info!("Failed to accept event stream client: {}", error);
+                    continue;
+                }
+            };
+            info!("Event stream client connected from {}", address);
+            let receiver = client_sender.subscribe();
+            tokio::spawn(async move {
+                if let Err(error) = serve_client(stream, receiver).await {
+                    info!("Event stream client {} disconnected: {}", address, error);
+                }
+            });
+        }
+    });
+
+    Ok(sender)
+}
+
+async fn serve_client(
+    mut stream: TcpStream,
+    mut receiver: broadcast::Receiver<Sample>,
+) -> Result<()> {
+    // The request itself doesn't matter, every path gets the event stream.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+    stream.write_all(RESPONSE_HEADER.as_bytes()).await?;
+
+    loop {
+        let sample = match receiver.recv().await {
+            Ok(sample) => sample,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        stream.write_all(format_event(&sample)?.as_bytes()).await?;
+    }
+}
+
+pub fn format_event(sample: &Sample) -> Result<String> {
+    Ok(format!("data: {}\n\n", serde_json::to_string(sample)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn samples_are_single_data_events() {
+        let sample = Sample {
+            timestamp: String::from("2026-01-01T00:00:00+00:00"),
+            heart_rate: 72,
+            rr_intervals: vec![830, 845],
+            contact: Some(true),
+            device: 1,
+            energy_expended: None,
+            hrv: None,
+        };
+        let event = format_event(&sample).unwrap();
+        let data = event.strip_prefix("data: ").unwrap();
+        let data = data.strip_suffix("\n\n").unwrap();
+        assert!(!data.contains('\n'));
+        let data: Value = serde_json::from_str(data).unwrap();
+        assert_eq!(data["heart_rate"], 72);
+        assert_eq!(data["rr_intervals"], json!([830, 845]));
+        assert_eq!(data["contact"], true);
+        assert_eq!(data["energy_expended"], Value::Null);
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::info;
+
+/// The latest state of the primary peripheral.
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct Status {
+    pub bpm: Option<u8>,
+    pub battery: Option<u8>,
+    pub connected: bool,
+    pub updated_at: Option<String>,
+}
+
+/// Serves the status sent on the returned channel as JSON on every path.
+pub async fn spawn(port: u16) -> Result<watch::Sender<Status>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Serving the status on port {}", port);
+
+    let (sender, receiver) = watch::channel(Status::default());
+    tokio::spawn(async move {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    info!("Failed to accept status client: {}", error);
+                    continue;
+                }
+            };
+            let status = receiver.borrow().clone();
+            tokio::spawn(async move {
+                if let Err(error) = serve_client(stream, &status).await {
+                    info!("Failed to serve the status to {}: {}", address, error);
+                }
+            });
+        }
+    });
+
+    Ok(sender)
+}
+
+async fn serve_client(mut stream: TcpStream, status: &Status) -> Result<()> {
+    // Like the event stream, the request itself doesn't matter.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+    stream
+        .write_all(format_response(status)?.as_bytes())
+        .await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+pub fn format_response(status: &Status) -> Result<String> {
+    let body = serde_json::to_string(status)?;
+    Ok(format!(
+        "HTTP/1.1 200 OK\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: {}\r\n\
+        Cache-Control: no-cache\r\n\
+        Connection: close\r\n\
+        Access-Control-Allow-Origin: *\r\n\r\n{}",
+        body.len(),
+        body
+    ))
+}
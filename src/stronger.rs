@@ -0,0 +1,99 @@
+use btleplug::api::BDAddr;
+use std::time::{Duration, Instant};
+
+/// Picks a peripheral to switch to once one has been significantly stronger
+/// than the connected peripheral for a sustained period.
+pub struct StrongerPeripheralTracker {
+    margin: i16,
+    duration: Duration,
+    candidate: Option<(BDAddr, Instant)>,
+}
+
+impl StrongerPeripheralTracker {
+    pub fn new(margin: i16, duration: Duration) -> Self {
+        Self {
+            margin,
+            duration,
+            candidate: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.candidate = None;
+    }
+
+    /// Returns the address to switch to, if any.
+    pub fn update(
+        &mut self,
+        current_rssi: i16,
+        candidates: &[(BDAddr, i16)],
+        now: Instant,
+    ) -> Option<BDAddr> {
+        let strongest = candidates
+            .iter()
+            .filter(|(_, rssi)| *rssi >= current_rssi.saturating_add(self.margin))
+            .max_by_key(|(_, rssi)| *rssi)
+            .map(|(address, _)| *address);
+
+        let Some(address) = strongest else {
+            self.candidate = None;
+            return None;
+        };
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == address => since,
+            _ => {
+                self.candidate = Some((address, now));
+                now
+            }
+        };
+
+        if now.duration_since(since) >= self.duration {
+            self.candidate = None;
+            Some(address)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8) -> BDAddr {
+        BDAddr::from([0, 0, 0, 0, 0, last])
+    }
+
+    #[test]
+    fn switches_once_sustainedly_stronger() {
+        let mut tracker = StrongerPeripheralTracker::new(10, Duration::from_secs(5));
+        let start = Instant::now();
+        let (current, stronger) = (address(1), address(2));
+        let candidates = [(current, -70), (stronger, -55)];
+        assert_eq!(tracker.update(-70, &candidates, start), None);
+        let almost = start + Duration::from_secs(4);
+        assert_eq!(tracker.update(-70, &candidates, almost), None);
+        let sustained = start + Duration::from_secs(5);
+        assert_eq!(tracker.update(-70, &candidates, sustained), Some(stronger));
+    }
+
+    #[test]
+    fn ignores_candidates_within_the_margin() {
+        let mut tracker = StrongerPeripheralTracker::new(10, Duration::ZERO);
+        let candidates = [(address(2), -61)];
+        assert_eq!(tracker.update(-70, &candidates, Instant::now()), None);
+    }
+
+    #[test]
+    fn restarts_when_the_candidate_weakens() {
+        let mut tracker = StrongerPeripheralTracker::new(10, Duration::from_secs(5));
+        let start = Instant::now();
+        let stronger = address(2);
+        tracker.update(-70, &[(stronger, -55)], start);
+        let weaker = start + Duration::from_secs(3);
+        assert_eq!(tracker.update(-70, &[(stronger, -65)], weaker), None);
+        let later = start + Duration::from_secs(6);
+        assert_eq!(tracker.update(-70, &[(stronger, -55)], later), None);
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Statistics of a session, gathered while it runs.
+pub struct SessionStats {
+    started_at: Instant,
+    samples: u64,
+    heart_rate_sum: u64,
+    min_heart_rate: Option<u8>,
+    max_heart_rate: Option<u8>,
+    connections: u64,
+    battery_start: Option<u8>,
+    battery_end: Option<u8>,
+    model: Option<String>,
+    firmware: Option<String>,
+}
+
+/// What `--run-summary` writes at shutdown.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RunSummary {
+    pub duration_seconds: f64,
+    pub samples: u64,
+    pub reconnects: u64,
+    pub dropped: u64,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub min_heart_rate: Option<u8>,
+    pub mean_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<u8>,
+    pub battery_start: Option<u8>,
+    pub battery_end: Option<u8>,
+}
+
+impl SessionStats {
+    pub fn new(started_at: Instant) -> Self {
+        Self {
+            started_at,
+            samples: 0,
+            heart_rate_sum: 0,
+            min_heart_rate: None,
+            max_heart_rate: None,
+            connections: 0,
+            battery_start: None,
+            battery_end: None,
+            model: None,
+            firmware: None,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        self.connections += 1;
+    }
+
+    pub fn battery(&mut self, level: u8) {
+        self.battery_start.get_or_insert(level);
+        self.battery_end = Some(level);
+    }
+
+    pub fn information(&mut self, model: Option<String>, firmware: Option<String>) {
+        self.model = model.or(self.model.take());
+        self.firmware = firmware.or(self.firmware.take());
+    }
+
+    pub fn sample(&mut self, heart_rate: u8) {
+        self.samples += 1;
+        self.heart_rate_sum += u64::from(heart_rate);
+        self.min_heart_rate = Some(
+            self.min_heart_rate
+                .map_or(heart_rate, |min| min.min(heart_rate)),
+        );
+        self.max_heart_rate = Some(
+            self.max_heart_rate
+                .map_or(heart_rate, |max| max.max(heart_rate)),
+        );
+    }
+
+    pub fn summary(&self, dropped: u64, now: Instant) -> RunSummary {
+        RunSummary {
+            duration_seconds: now
+                .checked_duration_since(self.started_at)
+                .unwrap_or(Duration::ZERO)
+                .as_secs_f64(),
+            samples: self.samples,
+            reconnects: self.connections.saturating_sub(1),
+            dropped,
+            model: self.model.clone(),
+            firmware: self.firmware.clone(),
+            min_heart_rate: self.min_heart_rate,
+            mean_heart_rate: (self.samples > 0)
+                .then(|| self.heart_rate_sum as f64 / self.samples as f64),
+            max_heart_rate: self.max_heart_rate,
+            battery_start: self.battery_start,
+            battery_end: self.battery_end,
+        }
+    }
+}
+
+impl RunSummary {
+    pub fn write(&self, path: &str) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn populated_stats_serialize_to_the_summary() {
+        let start = Instant::now();
+        let mut stats = SessionStats::new(start);
+        stats.connected();
+        stats.information(Some(String::from("H10")), None);
+        stats.battery(90);
+        for heart_rate in [60, 90, 75] {
+            stats.sample(heart_rate);
+        }
+        stats.connected();
+        stats.information(None, Some(String::from("5.0.0")));
+        stats.battery(85);
+        let summary = stats.summary(4, start + Duration::from_secs(90));
+        let expected = json!({
+            "duration_seconds": 90.0,
+            "samples": 3,
+            "reconnects": 1,
+            "dropped": 4,
+            "model": "H10",
+            "firmware": "5.0.0",
+            "min_heart_rate": 60,
+            "mean_heart_rate": 75.0,
+            "max_heart_rate": 90,
+            "battery_start": 90,
+            "battery_end": 85,
+        });
+        assert_eq!(serde_json::to_value(&summary).unwrap(), expected);
+    }
+
+    #[test]
+    fn empty_sessions_have_no_heart_rates() {
+        let start = Instant::now();
+        let summary = SessionStats::new(start).summary(0, start);
+        assert_eq!(summary.samples, 0);
+        assert_eq!(summary.reconnects, 0);
+        assert_eq!(summary.mean_heart_rate, None);
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+/// Becomes active once a condition has held continuously for `duration`, and
+/// clears as soon as it stops holding.
+pub struct SustainedState {
+    duration: Duration,
+    held_since: Option<Instant>,
+    active: bool,
+}
+
+impl SustainedState {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            held_since: None,
+            active: false,
+        }
+    }
+
+    /// Returns the new state when it changes.
+    pub fn update(&mut self, condition: bool, now: Instant) -> Option<bool> {
+        let active = if condition {
+            let held_since = *self.held_since.get_or_insert(now);
+            now.duration_since(held_since) >= self.duration
+        } else {
+            self.held_since = None;
+            false
+        };
+
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enters_once_held_for_the_duration() {
+        let mut calm = SustainedState::new(Duration::from_secs(30));
+        let start = Instant::now();
+        assert_eq!(calm.update(true, start), None);
+        assert_eq!(calm.update(true, start + Duration::from_secs(29)), None);
+        assert_eq!(
+            calm.update(true, start + Duration::from_secs(30)),
+            Some(true)
+        );
+        assert_eq!(calm.update(true, start + Duration::from_secs(31)), None);
+    }
+
+    #[test]
+    fn exits_as_soon_as_it_stops_holding() {
+        let mut calm = SustainedState::new(Duration::from_secs(30));
+        let start = Instant::now();
+        calm.update(true, start);
+        calm.update(true, start + Duration::from_secs(30));
+        assert_eq!(
+            calm.update(false, start + Duration::from_secs(31)),
+            Some(false)
+        );
+        assert_eq!(calm.update(false, start + Duration::from_secs(32)), None);
+    }
+
+    #[test]
+    fn interruptions_restart_the_duration() {
+        let mut calm = SustainedState::new(Duration::from_secs(30));
+        let start = Instant::now();
+        calm.update(true, start);
+        calm.update(false, start + Duration::from_secs(20));
+        assert_eq!(calm.update(true, start + Duration::from_secs(21)), None);
+        assert_eq!(calm.update(true, start + Duration::from_secs(50)), None);
+        assert_eq!(
+            calm.update(true, start + Duration::from_secs(51)),
+            Some(true)
+        );
+    }
+}
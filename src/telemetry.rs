@@ -0,0 +1,860 @@
+use crate::calories::{CalorieCounter, Sex};
+use crate::effort::EffortTracker;
+use crate::features::OscFeature;
+use crate::gauge::GaugeEasing;
+use crate::heartbeat::HeartBeat;
+use crate::hrv::Rmssd;
+use crate::measurement::HeartRateMeasurement;
+use crate::normalize::{self, PercentileRange, SeededRange};
+use crate::osc;
+use crate::preset::HeartRateParameters;
+use crate::pulse::Pulse;
+use crate::sample::Sample;
+use crate::smoothing::ExponentialMovingAverage;
+use crate::spike::SpikeDetector;
+use crate::sustained::SustainedState;
+use crate::template::StringTemplate;
+use crate::zone;
+use anyhow::{bail, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::time::{Duration, Instant};
+
+/// What the OSC parameters of a session are computed from.
+pub struct TelemetryOptions {
+    pub outputs: Vec<OscFeature>,
+    pub osc_prefix: String,
+    pub heart_rate_parameters: HeartRateParameters,
+    pub min_heart_rate: u16,
+    pub max_heart_rate: u16,
+    pub zone_max: u16,
+    pub init_value: Option<f32>,
+    pub reset_smoothing_on_reconnect: bool,
+    pub combined_message: Option<String>,
+    pub string_output: Option<(String, StringTemplate)>,
+    pub calm_bpm: Option<u16>,
+    pub calm_duration: Duration,
+    pub spike_delta: Option<f32>,
+    /// Age, weight in kilograms and sex.
+    pub calories: Option<(u8, f32, Sex)>,
+    /// Resting and maximum heart rate.
+    pub effort: Option<(u8, u8)>,
+    pub retention: usize,
+    /// Resting heart rate and the rise above it that is 1.0.
+    pub baseline_delta: Option<(u8, u8)>,
+    pub gauge_ease: Option<Duration>,
+    pub smoothing: Option<f32>,
+    pub auto_seed_range: Option<Duration>,
+    pub percentile_window: Option<usize>,
+    pub hrv_window: Option<Duration>,
+    /// The RMSSD in milliseconds that is 1.0.
+    pub hrv_max: f32,
+}
+
+/// Everything the OSC parameters of a session depend on besides the latest
+/// measurement.
+pub struct SessionState {
+    options: TelemetryOptions,
+    gauge: Option<GaugeEasing>,
+    smoothing: Option<ExponentialMovingAverage>,
+    seeded_range: Option<SeededRange>,
+    percentile_range: Option<PercentileRange>,
+    heart_beat: HeartBeat,
+    calm: SustainedState,
+    spike: Option<SpikeDetector>,
+    spike_pulse: Pulse,
+    reconnect_pulse: Pulse,
+    started_pulse: Pulse,
+    calories: Option<CalorieCounter>,
+    calories_sent: Option<i32>,
+    effort: Option<EffortTracker>,
+    /// The heart rate and its normalized value last sent, for keepalives.
+    last_heart_rate: Option<(u8, f32)>,
+    /// The zone last sent, kept across disconnects for avatar changes.
+    last_zone: Option<u8>,
+    connected: bool,
+    hrv: Option<Rmssd>,
+    /// Cleared once the peripheral turns out not to report RR intervals, so
+    /// the outputs depending on them are approximated from the heart rate.
+    rr_available: bool,
+}
+
+impl SessionState {
+    pub fn new(options: TelemetryOptions) -> Result<Self> {
+        let enabled = |feature| options.outputs.contains(&feature);
+        let calories = options
+            .calories
+            .filter(|_| enabled(OscFeature::Calories))
+            .map(|(age, weight_kg, sex)| CalorieCounter::new(age, weight_kg, sex));
+        let effort = match options.effort.filter(|_| enabled(OscFeature::Effort)) {
+            Some((resting_hr, max_hr)) if resting_hr >= max_hr => {
+                bail!("--resting-hr must be lower than --max-hr");
+            }
+            Some((resting_hr, max_hr)) => {
+                Some(EffortTracker::new(resting_hr, max_hr, options.retention))
+            }
+            None => None,
+        };
+        let spike = options
+            .spike_delta
+            .filter(|_| enabled(OscFeature::Spike))
+            .map(SpikeDetector::new);
+        Ok(Self {
+            gauge: options.gauge_ease.map(GaugeEasing::new),
+            smoothing: options
+                .smoothing
+                .filter(|alpha| *alpha > 0.0)
+                .map(ExponentialMovingAverage::new),
+            seeded_range: options.auto_seed_range.map(SeededRange::new),
+            percentile_range: options
+                .percentile_window
+                .map(|window| PercentileRange::new(window.min(options.retention))),
+            heart_beat: HeartBeat::new(),
+            calm: SustainedState::new(options.calm_duration),
+            spike,
+            spike_pulse: Pulse::default(),
+            reconnect_pulse: Pulse::default(),
+            started_pulse: Pulse::default(),
+            calories,
+            calories_sent: None,
+            effort,
+            last_heart_rate: None,
+            last_zone: None,
+            connected: false,
+            hrv: options
+                .hrv_window
+                .filter(|_| enabled(OscFeature::Hrv))
+                .map(|window| Rmssd::new(window, options.retention)),
+            rr_available: true,
+            options,
+        })
+    }
+
+    fn enabled(&self, feature: OscFeature) -> bool {
+        self.options.outputs.contains(&feature)
+    }
+
+    /// The address of a parameter under --osc-prefix.
+    fn parameter(&self, suffix: &str) -> String {
+        format!("{}{}", self.options.osc_prefix, suffix)
+    }
+
+    /// The addresses of the enabled parameters that are in [0.0, 1.0].
+    pub fn unit_range_addresses(&self) -> Vec<String> {
+        let mut addresses = vec![];
+        if self.enabled(OscFeature::Bpm) {
+            addresses.extend(self.options.heart_rate_parameters.unit_range_addresses());
+        }
+        for (feature, suffix) in [
+            (OscFeature::Battery, "Battery"),
+            (OscFeature::Delta, "Delta"),
+            (OscFeature::Effort, "EffortIndex"),
+            (OscFeature::Hrv, "HRV"),
+        ] {
+            if self.enabled(feature) {
+                addresses.push(self.parameter(suffix));
+            }
+        }
+        addresses.extend(self.options.combined_message.clone());
+        addresses
+    }
+
+    /// Starts over for a new connection, returning what to send right away.
+    pub fn connected(&mut self, reconnected: bool) -> Vec<OscPacket> {
+        let mut packets = vec![];
+        self.connected = true;
+        self.heart_beat.reset();
+        if let Some(hrv) = &mut self.hrv {
+            hrv.reset();
+        }
+        if self.options.reset_smoothing_on_reconnect {
+            if let Some(gauge) = &mut self.gauge {
+                gauge.reset();
+            }
+            if let Some(smoothing) = &mut self.smoothing {
+                smoothing.reset();
+            }
+        }
+        if self.enabled(OscFeature::Reconnected) && reconnected {
+            // Sent with the next measurement, so it's only true for a single
+            // update.
+            self.reconnect_pulse.trigger();
+        }
+        if let (Some(init_value), true) = (self.options.init_value, self.enabled(OscFeature::Bpm)) {
+            // The first sample is taken as is rather than eased toward from
+            // here.
+            if let Some(gauge) = &mut self.gauge {
+                gauge.reset();
+            }
+            let messages = self
+                .options
+                .heart_rate_parameters
+                .normalized_messages(init_value);
+            packets.extend(parameter_messages(messages));
+        }
+        packets.extend(self.zone_boundaries());
+        packets
+    }
+
+    fn zone_boundaries(&self) -> Vec<OscPacket> {
+        if !self.enabled(OscFeature::Zone) {
+            return vec![];
+        }
+        let boundaries = zone::boundaries(self.options.zone_max);
+        let messages = boundaries.iter().enumerate().map(|(index, boundary)| {
+            let address = self.parameter(&format!("Zone{}Min", index));
+            message(address, vec![OscType::Int(i32::from(*boundary))])
+        });
+        messages.collect()
+    }
+
+    pub fn disconnected(&mut self) {
+        self.connected = false;
+        self.heart_beat.reset();
+        self.last_heart_rate = None;
+        if let Some(hrv) = &mut self.hrv {
+            hrv.reset();
+        }
+    }
+
+    pub fn set_rr_available(&mut self, available: bool) {
+        self.rr_available = available;
+    }
+
+    /// The latest RMSSD, once there are enough RR intervals for it.
+    pub fn hrv(&self) -> Option<f32> {
+        self.hrv.as_ref().and_then(Rmssd::value)
+    }
+
+    pub fn battery(&self, level: u8) -> Vec<OscPacket> {
+        if !self.enabled(OscFeature::Battery) {
+            return vec![];
+        }
+        let normalized = f32::from(level.min(100)) / 100.0;
+        vec![
+            message(self.parameter("Battery"), vec![OscType::Float(normalized)]),
+            message(
+                self.parameter("BatteryInt"),
+                vec![OscType::Int(i32::from(level))],
+            ),
+        ]
+    }
+
+    /// Sends the last heart rate, battery level and connection state again,
+    /// so they aren't dropped while the peripheral is quiet. Nothing is sent
+    /// before the first measurement of a connection.
+    pub fn keepalive(&self, battery_level: Option<u8>, now: Instant) -> Vec<OscPacket> {
+        if self.last_heart_rate.is_none() {
+            return vec![];
+        }
+        let mut packets = self.last_heart_rate_messages(now);
+        if let Some(level) = battery_level {
+            packets.extend(self.battery(level));
+        }
+        packets.extend(self.connection(true));
+        packets
+    }
+
+    fn last_heart_rate_messages(&self, now: Instant) -> Vec<OscPacket> {
+        let Some((beats_per_minute, percent)) = self.last_heart_rate else {
+            return vec![];
+        };
+        let parameters = &self.options.heart_rate_parameters;
+        let mut messages = parameters.beats_per_minute_messages(beats_per_minute);
+        let percent = self
+            .gauge
+            .as_ref()
+            .map_or(percent, |gauge| gauge.value(now));
+        messages.extend(parameters.normalized_messages(percent));
+        parameter_messages(messages)
+    }
+
+    /// Sends what a new avatar needs right away, as its parameters start
+    /// over: the heart rate while it's streaming, and the last known battery
+    /// level, connection state and zone with its boundaries even before the
+    /// first measurement or after a disconnect.
+    pub fn avatar_changed(&self, battery_level: Option<u8>, now: Instant) -> Vec<OscPacket> {
+        let mut packets = self.last_heart_rate_messages(now);
+        if let Some(level) = battery_level {
+            packets.extend(self.battery(level));
+        }
+        packets.extend(self.connection(self.connected));
+        packets.extend(self.zone_boundaries());
+        if let (Some(zone), true) = (self.last_zone, self.enabled(OscFeature::Zone)) {
+            packets.push(message(
+                self.parameter("Zone"),
+                vec![OscType::Int(i32::from(zone))],
+            ));
+        }
+        packets
+    }
+
+    pub fn connection(&self, connected: bool) -> Vec<OscPacket> {
+        if !self.enabled(OscFeature::Connected) {
+            return vec![];
+        }
+        vec![message(
+            self.parameter("Connected"),
+            vec![OscType::Bool(connected)],
+        )]
+    }
+
+    /// Marks the next measurement as the first one of the connection.
+    pub fn started(&mut self) {
+        if self.enabled(OscFeature::Started) {
+            self.started_pulse.trigger();
+        }
+    }
+
+    /// Whether the HeartRate float eases and needs [`Self::gauge_tick`].
+    pub fn eases(&self) -> bool {
+        self.gauge.is_some() && self.enabled(OscFeature::Bpm)
+    }
+
+    pub fn gauge_tick(&mut self, now: Instant) -> Vec<OscPacket> {
+        let Some(value) = self.gauge.as_mut().and_then(|gauge| gauge.tick(now)) else {
+            return vec![];
+        };
+        let messages = self
+            .options
+            .heart_rate_parameters
+            .normalized_messages(value);
+        parameter_messages(messages)
+    }
+
+    /// When the next HeartBeat toggle is due, if any is scheduled.
+    pub fn next_beat(&self) -> Option<Instant> {
+        self.heart_beat.next_at()
+    }
+
+    pub fn beat(&mut self) -> OscPacket {
+        let beat = self.heart_beat.toggle();
+        message(self.parameter("Beat"), vec![OscType::Bool(beat)])
+    }
+}
+
+/// Computes the OSC parameters of a raw Heart Rate Measurement value, of a
+/// single peripheral whose energy expended is sent as reported. Malformed
+/// values produce nothing. The main loop parses notifications itself, as it
+/// merges and logs them too, so only tests start from the raw bytes.
+#[cfg(test)]
+pub fn process_raw_measurement(value: &[u8], state: &mut SessionState) -> Vec<OscPacket> {
+    let Some(measurement) = crate::measurement::parse_heart_rate_measurement(value) else {
+        return vec![];
+    };
+    let beats_per_minute = measurement.beats_per_minute();
+    let energy_expended = measurement.energy_expended.map(u64::from);
+    process_measurement(&measurement, beats_per_minute, energy_expended, None, state)
+}
+
+/// Computes the OSC parameters of a measurement. The heart rate is passed
+/// separately as it may be merged from several peripherals, and the energy
+/// expended as it's accumulated across the wraparounds of the raw counter.
+pub fn process_measurement(
+    measurement: &HeartRateMeasurement,
+    beats_per_minute: u8,
+    energy_expended: Option<u64>,
+    battery_level: Option<u8>,
+    state: &mut SessionState,
+) -> Vec<OscPacket> {
+    let mut packets = vec![];
+    let now = Instant::now();
+
+    // OSC gets the smoothed heart rate, the logs keep the raw one.
+    let smoothed = state
+        .smoothing
+        .as_mut()
+        .map(|smoothing| smoothing.update(f32::from(beats_per_minute)));
+    let sent_beats_per_minute =
+        smoothed.map_or(beats_per_minute, |smoothed| smoothed.round() as u8);
+    let mut percent = normalize::normalize_between(
+        u16::from(sent_beats_per_minute),
+        state.options.min_heart_rate,
+        state.options.max_heart_rate,
+    );
+    if let Some(range) = &mut state.percentile_range {
+        let heart_rate = u16::from(sent_beats_per_minute);
+        range.push(heart_rate);
+        percent = range.normalize(heart_rate).unwrap_or(percent);
+    }
+    if let Some(range) = &mut state.seeded_range {
+        let heart_rate = u16::from(sent_beats_per_minute);
+        percent = range.normalize(heart_rate, now).unwrap_or(percent);
+    }
+    if state.enabled(OscFeature::Bpm) {
+        let parameters = &state.options.heart_rate_parameters;
+        let mut messages = parameters.beats_per_minute_messages(sent_beats_per_minute);
+        match &mut state.gauge {
+            Some(gauge) => gauge.set_target(percent, now),
+            None => messages.extend(parameters.normalized_messages(percent)),
+        }
+        packets.extend(parameter_messages(messages));
+        state.last_heart_rate = Some((sent_beats_per_minute, percent));
+    }
+
+    if state.enabled(OscFeature::Zone) {
+        let zone = zone::zone(u16::from(sent_beats_per_minute), state.options.zone_max);
+        state.last_zone = Some(zone);
+        packets.push(message(
+            state.parameter("Zone"),
+            vec![OscType::Int(i32::from(zone))],
+        ));
+    }
+
+    if state.enabled(OscFeature::HeartBeat) {
+        // Without RR intervals the beats are timed from the heart rate.
+        let rr_intervals = match state.rr_available {
+            true => &measurement.rr_intervals[..],
+            false => &[],
+        };
+        state
+            .heart_beat
+            .schedule(rr_intervals, sent_beats_per_minute, now);
+    }
+
+    // Without RR intervals nothing is sent, rather than a made up variability.
+    if let (Some(hrv), true) = (&mut state.hrv, state.rr_available) {
+        let rr_intervals_ms = measurement.rr_intervals_ms();
+        if !rr_intervals_ms.is_empty() {
+            hrv.extend(&rr_intervals_ms);
+            if let Some(rmssd) = hrv.value() {
+                let normalized = (rmssd / state.options.hrv_max).clamp(0.0, 1.0);
+                packets.push(message(
+                    state.parameter("HRV"),
+                    vec![OscType::Float(normalized)],
+                ));
+            }
+        }
+    }
+
+    if let (Some(smoothed), true) = (smoothed, state.enabled(OscFeature::Smoothed)) {
+        packets.push(message(
+            state.parameter("Smoothed"),
+            vec![OscType::Float(smoothed)],
+        ));
+    }
+
+    if let Some(address) = &state.options.combined_message {
+        let combined = osc::combined_arguments(sent_beats_per_minute, percent, battery_level);
+        packets.push(message(address.clone(), combined));
+    }
+
+    if let Some((address, template)) = &state.options.string_output {
+        let rendered = template.render(beats_per_minute, battery_level);
+        packets.push(message(address.clone(), vec![OscType::String(rendered)]));
+    }
+
+    if let (Some(energy), true) = (energy_expended, state.enabled(OscFeature::Energy)) {
+        let energy = i32::try_from(energy).unwrap_or(i32::MAX);
+        packets.push(message(
+            state.parameter("Energy"),
+            vec![OscType::Int(energy)],
+        ));
+    }
+
+    if state.enabled(OscFeature::Debug) {
+        packets.push(message(
+            state.parameter("Debug"),
+            vec![OscType::String(measurement.debug_string())],
+        ));
+    }
+
+    let calm_bpm = state
+        .options
+        .calm_bpm
+        .filter(|_| state.enabled(OscFeature::Calm));
+    if let Some(calm_bpm) = calm_bpm {
+        let is_below = u16::from(beats_per_minute) < calm_bpm;
+        if let Some(is_calm) = state.calm.update(is_below, now) {
+            packets.push(message(
+                state.parameter("Calm"),
+                vec![OscType::Bool(is_calm)],
+            ));
+        }
+    }
+
+    if let Some(spike) = &mut state.spike {
+        if spike.update(beats_per_minute) {
+            state.spike_pulse.trigger();
+        }
+        if let Some(is_spike) = state.spike_pulse.update() {
+            packets.push(message(
+                state.parameter("Spike"),
+                vec![OscType::Bool(is_spike)],
+            ));
+        }
+    }
+
+    if let Some(reconnected) = state.reconnect_pulse.update() {
+        packets.push(message(
+            state.parameter("Reconnected"),
+            vec![OscType::Bool(reconnected)],
+        ));
+    }
+
+    if let Some(started) = state.started_pulse.update() {
+        packets.push(message(
+            state.parameter("Started"),
+            vec![OscType::Bool(started)],
+        ));
+    }
+
+    if let Some(calories) = &mut state.calories {
+        let total = calories.update(beats_per_minute, now) as i32;
+        if state.calories_sent != Some(total) {
+            packets.push(message(
+                state.parameter("Calories"),
+                vec![OscType::Int(total)],
+            ));
+            state.calories_sent = Some(total);
+        }
+    }
+
+    let baseline_delta = state
+        .options
+        .baseline_delta
+        .filter(|_| state.enabled(OscFeature::Delta));
+    if let Some((resting_hr, delta_max)) = baseline_delta {
+        let delta = normalize::baseline_delta(beats_per_minute, resting_hr, delta_max);
+        packets.push(message(
+            state.parameter("Delta"),
+            vec![OscType::Float(delta)],
+        ));
+    }
+
+    if let Some(effort) = &mut state.effort {
+        if let Some(index) = effort.update(beats_per_minute, now) {
+            packets.push(message(
+                state.parameter("EffortIndex"),
+                vec![OscType::Float(index)],
+            ));
+        }
+    }
+
+    packets
+}
+
+/// The CSV columns of a sample, in the order of [`crate::sink::CSV_HEADER`].
+pub fn csv_record(sample: &Sample) -> [String; 7] {
+    [
+        sample.timestamp.clone(),
+        sample.heart_rate.to_string(),
+        sample.rr_intervals_column(),
+        sample.contact_column().to_string(),
+        sample.device.to_string(),
+        sample
+            .energy_expended
+            .map_or(String::new(), |energy| energy.to_string()),
+        sample
+            .hrv
+            .map_or(String::new(), |hrv| format!("{:.1}", hrv)),
+    ]
+}
+
+fn parameter_messages(messages: Vec<(String, OscType)>) -> Vec<OscPacket> {
+    messages
+        .into_iter()
+        .map(|(address, argument)| message(address, vec![argument]))
+        .collect()
+}
+
+fn message(address: impl Into<String>, args: Vec<OscType>) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: address.into(),
+        args,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::measurement::parse_heart_rate_measurement;
+    use crate::preset;
+
+    const PREFIX: &str = "/avatar/parameters/HeartRate";
+
+    fn state(outputs: &[OscFeature]) -> SessionState {
+        SessionState::new(options(outputs)).unwrap()
+    }
+
+    /// Options sending `outputs` under the default prefix, with everything
+    /// else off.
+    pub(crate) fn options(outputs: &[OscFeature]) -> TelemetryOptions {
+        let default = preset::find("default").unwrap();
+        TelemetryOptions {
+            outputs: outputs.to_vec(),
+            osc_prefix: String::from(PREFIX),
+            heart_rate_parameters: HeartRateParameters::new(default, None, PREFIX),
+            min_heart_rate: 0,
+            max_heart_rate: 200,
+            zone_max: 200,
+            init_value: None,
+            reset_smoothing_on_reconnect: false,
+            combined_message: None,
+            string_output: None,
+            calm_bpm: None,
+            calm_duration: Duration::from_secs(30),
+            spike_delta: None,
+            calories: None,
+            effort: None,
+            retention: 3600,
+            baseline_delta: None,
+            gauge_ease: None,
+            smoothing: None,
+            auto_seed_range: None,
+            percentile_window: None,
+            hrv_window: Some(Duration::from_secs(60)),
+            hrv_max: 100.0,
+        }
+    }
+
+    fn arguments(packets: &[OscPacket], address: &str) -> Vec<Vec<OscType>> {
+        packets
+            .iter()
+            .filter_map(|packet| match packet {
+                OscPacket::Message(message) if message.addr == address => {
+                    Some(message.args.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_measurement_to_osc_and_csv() {
+        let outputs = [OscFeature::Bpm, OscFeature::Zone, OscFeature::Energy];
+        let mut state = state(&outputs);
+        // A 16-bit heart rate of 150 with contact, 300 kJ and an RR interval.
+        let value = [0x1f, 0x96, 0x00, 0x2c, 0x01, 0x00, 0x04];
+        let measurement = parse_heart_rate_measurement(&value).unwrap();
+        let beats_per_minute = measurement.beats_per_minute();
+        let packets =
+            process_measurement(&measurement, beats_per_minute, Some(0), None, &mut state);
+
+        let sent: Vec<_> = packets
+            .iter()
+            .filter_map(|packet| match packet {
+                OscPacket::Message(message) => Some((message.addr.as_str(), message.args.clone())),
+                OscPacket::Bundle(_) => None,
+            })
+            .collect();
+        let expected = [
+            ("/avatar/parameters/HeartRateInt", vec![OscType::Int(150)]),
+            ("/avatar/parameters/HeartRate", vec![OscType::Float(0.75)]),
+            ("/avatar/parameters/HeartRateZone", vec![OscType::Int(2)]),
+            ("/avatar/parameters/HeartRateEnergy", vec![OscType::Int(0)]),
+        ];
+        assert_eq!(sent, expected);
+
+        let sample = Sample {
+            energy_expended: Some(0),
+            ..Sample::new(
+                String::from("2026-01-01T00:00:00+00:00"),
+                beats_per_minute,
+                &measurement,
+                1,
+            )
+        };
+        let expected = [
+            "2026-01-01T00:00:00+00:00",
+            "150",
+            "1000",
+            "1",
+            "1",
+            "0",
+            "",
+        ];
+        assert_eq!(csv_record(&sample), expected.map(String::from));
+    }
+
+    #[test]
+    fn hrv_is_normalized_under_the_prefix() {
+        let mut state = state(&[OscFeature::Hrv]);
+        let mut sent = vec![];
+        // RR intervals alternating between 800 and 850 ms, an RMSSD of 50 ms.
+        for rr_interval in [819, 870].repeat(6) {
+            let measurement = HeartRateMeasurement {
+                flags: 0x10,
+                heart_rate: 72,
+                sensor_contact: None,
+                energy_expended: None,
+                rr_intervals: vec![rr_interval],
+            };
+            sent.extend(process_measurement(
+                &measurement,
+                72,
+                None,
+                None,
+                &mut state,
+            ));
+        }
+        let hrv = arguments(&sent, "/avatar/parameters/HeartRateHRV");
+        let Some([OscType::Float(normalized)]) = hrv.last().map(Vec::as_slice) else {
+            panic!("No HeartRateHRV sent: {:?}", sent);
+        };
+        assert!((normalized - 0.5).abs() < 0.01, "{}", normalized);
+    }
+
+    #[test]
+    fn hrv_is_not_sent_without_rr_intervals() {
+        let mut state = state(&[OscFeature::Hrv]);
+        state.set_rr_available(false);
+        let sent: Vec<_> = [72, 80, 66, 90, 70, 84]
+            .into_iter()
+            .flat_map(|beats_per_minute| measure(&mut state, beats_per_minute))
+            .collect();
+        assert!(arguments(&sent, "/avatar/parameters/HeartRateHRV").is_empty());
+    }
+
+    #[test]
+    fn avatar_changes_resend_the_last_known_state() {
+        let outputs = [
+            OscFeature::Bpm,
+            OscFeature::Battery,
+            OscFeature::Connected,
+            OscFeature::Zone,
+        ];
+        let mut state = state(&outputs);
+        let now = Instant::now();
+
+        // Before the first measurement.
+        let sent = state.avatar_changed(Some(80), now);
+        let connected = "/avatar/parameters/HeartRateConnected";
+        assert_eq!(arguments(&sent, connected), [[OscType::Bool(false)]]);
+        let battery = "/avatar/parameters/HeartRateBatteryInt";
+        assert_eq!(arguments(&sent, battery), [[OscType::Int(80)]]);
+        assert_eq!(
+            arguments(&sent, "/avatar/parameters/HeartRateZone0Min").len(),
+            1
+        );
+        assert!(arguments(&sent, "/avatar/parameters/HeartRateZone").is_empty());
+
+        state.connected(false);
+        let measurement = parse_heart_rate_measurement(&[0x00, 150]).unwrap();
+        process_measurement(&measurement, 150, None, Some(80), &mut state);
+        let sent = state.avatar_changed(Some(80), now);
+        assert_eq!(arguments(&sent, connected), [[OscType::Bool(true)]]);
+        let heart_rate = "/avatar/parameters/HeartRateInt";
+        assert_eq!(arguments(&sent, heart_rate), [[OscType::Int(150)]]);
+        let zone = "/avatar/parameters/HeartRateZone";
+        assert_eq!(arguments(&sent, zone), [[OscType::Int(2)]]);
+
+        // After a disconnect, everything but the stale heart rate.
+        state.disconnected();
+        let sent = state.avatar_changed(Some(80), now);
+        assert_eq!(arguments(&sent, connected), [[OscType::Bool(false)]]);
+        assert_eq!(arguments(&sent, battery), [[OscType::Int(80)]]);
+        assert_eq!(arguments(&sent, zone), [[OscType::Int(2)]]);
+        assert!(arguments(&sent, heart_rate).is_empty());
+    }
+
+    #[test]
+    fn heart_beat_is_under_the_prefix() {
+        let mut state = state(&[OscFeature::HeartBeat]);
+        let beat = state.beat();
+        assert_eq!(
+            arguments(&[beat], "/avatar/parameters/HeartRateBeat"),
+            [[OscType::Bool(true)]]
+        );
+    }
+
+    #[test]
+    fn raw_bytes_to_osc() {
+        let mut state = state(&[OscFeature::Bpm]);
+        let packets = process_raw_measurement(&[0x00, 72], &mut state);
+        assert_eq!(
+            arguments(&packets, "/avatar/parameters/HeartRateInt"),
+            [[OscType::Int(72)]]
+        );
+        assert_eq!(
+            arguments(&packets, "/avatar/parameters/HeartRate"),
+            [[OscType::Float(0.36)]]
+        );
+        // A 16-bit heart rate cut short.
+        assert!(process_raw_measurement(&[0x01, 72], &mut state).is_empty());
+    }
+
+    #[test]
+    fn string_output_renders_the_template() {
+        let mut state = state(&[]);
+        let template = StringTemplate::parse("{bpm} ({battery}%)").unwrap();
+        state.options.string_output = Some((String::from("/heartrate/text"), template));
+        let measurement = parse_heart_rate_measurement(&[0x00, 72]).unwrap();
+        let packets = process_measurement(&measurement, 72, None, Some(40), &mut state);
+        assert_eq!(
+            arguments(&packets, "/heartrate/text"),
+            [[OscType::String(String::from("72 (40%)"))]]
+        );
+    }
+
+    fn measure(state: &mut SessionState, beats_per_minute: u8) -> Vec<OscPacket> {
+        let measurement = parse_heart_rate_measurement(&[0x00, beats_per_minute]).unwrap();
+        process_measurement(&measurement, beats_per_minute, None, None, state)
+    }
+
+    fn smoothed(state: &mut SessionState, beats_per_minute: u8) -> Vec<Vec<OscType>> {
+        let packets = measure(state, beats_per_minute);
+        arguments(&packets, "/avatar/parameters/HeartRateSmoothed")
+    }
+
+    #[test]
+    fn smoothing_starts_over_on_reconnect() {
+        let mut state = SessionState::new(TelemetryOptions {
+            smoothing: Some(0.5),
+            reset_smoothing_on_reconnect: true,
+            ..options(&[OscFeature::Smoothed])
+        })
+        .unwrap();
+        assert_eq!(smoothed(&mut state, 60), [[OscType::Float(60.0)]]);
+        assert_eq!(smoothed(&mut state, 100), [[OscType::Float(80.0)]]);
+        state.connected(true);
+        assert_eq!(smoothed(&mut state, 120), [[OscType::Float(120.0)]]);
+    }
+
+    #[test]
+    fn smoothing_carries_over_reconnects_unless_reset() {
+        let mut state = SessionState::new(TelemetryOptions {
+            smoothing: Some(0.5),
+            ..options(&[OscFeature::Smoothed])
+        })
+        .unwrap();
+        assert_eq!(smoothed(&mut state, 60), [[OscType::Float(60.0)]]);
+        state.connected(true);
+        assert_eq!(smoothed(&mut state, 100), [[OscType::Float(80.0)]]);
+    }
+
+    #[test]
+    fn reconnecting_pulses_once() {
+        let mut state = state(&[OscFeature::Reconnected]);
+        let reconnected = |state: &mut SessionState| {
+            let packets = measure(state, 72);
+            arguments(&packets, "/avatar/parameters/HeartRateReconnected")
+        };
+        state.connected(false);
+        assert!(reconnected(&mut state).is_empty());
+        state.connected(true);
+        assert_eq!(reconnected(&mut state), [[OscType::Bool(true)]]);
+        assert_eq!(reconnected(&mut state), [[OscType::Bool(false)]]);
+        assert!(reconnected(&mut state).is_empty());
+        assert!(reconnected(&mut state).is_empty());
+    }
+
+    #[test]
+    fn init_value_is_sent_on_connect_then_overridden() {
+        let mut state = SessionState::new(TelemetryOptions {
+            init_value: Some(0.5),
+            ..options(&[OscFeature::Bpm])
+        })
+        .unwrap();
+        let heart_rate = |packets: &[OscPacket]| arguments(packets, PREFIX);
+        assert_eq!(heart_rate(&state.connected(false)), [[OscType::Float(0.5)]]);
+        assert_eq!(
+            heart_rate(&measure(&mut state, 150)),
+            [[OscType::Float(0.75)]]
+        );
+        assert_eq!(
+            heart_rate(&measure(&mut state, 100)),
+            [[OscType::Float(0.5)]]
+        );
+        assert_eq!(heart_rate(&state.connected(true)), [[OscType::Float(0.5)]]);
+    }
+}
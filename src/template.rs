@@ -0,0 +1,79 @@
+use anyhow::{bail, Result};
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    HeartRate,
+    Battery,
+}
+
+/// A message template with `{bpm}` and `{battery}` placeholders.
+#[derive(Debug, PartialEq)]
+pub struct StringTemplate {
+    pieces: Vec<Piece>,
+}
+
+impl StringTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                pieces.push(Piece::Literal(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                bail!("Unclosed placeholder in template {:?}", template);
+            };
+            let placeholder = &rest[start + 1..start + end];
+            pieces.push(match placeholder {
+                "bpm" => Piece::HeartRate,
+                "battery" => Piece::Battery,
+                _ => bail!(
+                    "Unknown placeholder {{{}}} in template {:?}, expected {{bpm}} or {{battery}}",
+                    placeholder,
+                    template
+                ),
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            bail!("Unopened placeholder in template {:?}", template);
+        }
+        if !rest.is_empty() {
+            pieces.push(Piece::Literal(rest.to_string()));
+        }
+        Ok(Self { pieces })
+    }
+
+    pub fn render(&self, heart_rate: u8, battery: Option<u8>) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Literal(literal) => literal.clone(),
+                Piece::HeartRate => heart_rate.to_string(),
+                Piece::Battery => battery.map_or(String::from("-"), |battery| battery.to_string()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_placeholders() {
+        let template = StringTemplate::parse("{bpm} bpm, battery {battery}%").unwrap();
+        assert_eq!(template.render(72, Some(85)), "72 bpm, battery 85%");
+        assert_eq!(template.render(72, None), "72 bpm, battery -%");
+        let template = StringTemplate::parse("plain").unwrap();
+        assert_eq!(template.render(72, None), "plain");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(StringTemplate::parse("{bpm").is_err());
+        assert!(StringTemplate::parse("bpm}").is_err());
+        assert!(StringTemplate::parse("{heart_rate}").is_err());
+    }
+}
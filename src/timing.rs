@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+const NOTIFICATION_BUCKET_MS: u64 = 100;
+const RR_INTERVAL_BUCKET_MS: u64 = 50;
+
+/// Counts intervals in buckets of `bucket_ms` milliseconds, keyed by the
+/// start of each bucket.
+pub fn bucket_intervals(
+    intervals: impl IntoIterator<Item = Duration>,
+    bucket_ms: u64,
+) -> BTreeMap<u64, usize> {
+    let mut buckets = BTreeMap::new();
+    for interval in intervals {
+        let millis = interval.as_millis() as u64;
+        *buckets.entry(millis - millis % bucket_ms).or_insert(0) += 1;
+    }
+    buckets
+}
+
+/// A histogram of intervals that only keeps the bucket counts.
+pub struct IntervalHistogram {
+    bucket_ms: u64,
+    buckets: BTreeMap<u64, usize>,
+    total: usize,
+}
+
+impl IntervalHistogram {
+    pub fn new(bucket_ms: u64) -> Self {
+        Self {
+            bucket_ms,
+            buckets: BTreeMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, interval: Duration) {
+        for (bucket, count) in bucket_intervals([interval], self.bucket_ms) {
+            *self.buckets.entry(bucket).or_insert(0) += count;
+        }
+        self.total += 1;
+    }
+}
+
+impl fmt::Display for IntervalHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.total == 0 {
+            return writeln!(f, "  -");
+        }
+        for (bucket, count) in &self.buckets {
+            writeln!(
+                f,
+                "  {:>5}-{:<5} ms {:>5} {}",
+                bucket,
+                bucket + self.bucket_ms - 1,
+                count,
+                "#".repeat(count * 40 / self.total)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Intervals between notifications and between RR-derived beats over a session.
+pub struct TimingReport {
+    pub notifications: IntervalHistogram,
+    pub rr_intervals: IntervalHistogram,
+}
+
+impl TimingReport {
+    pub fn new() -> Self {
+        Self {
+            notifications: IntervalHistogram::new(NOTIFICATION_BUCKET_MS),
+            rr_intervals: IntervalHistogram::new(RR_INTERVAL_BUCKET_MS),
+        }
+    }
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Notification intervals:")?;
+        write!(f, "{}", self.notifications)?;
+        writeln!(f, "RR intervals:")?;
+        write!(f, "{}", self.rr_intervals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_fall_into_buckets_by_their_start() {
+        let intervals = [0, 99, 100, 950, 1010, 1099].map(Duration::from_millis);
+        let buckets = bucket_intervals(intervals, 100);
+        assert_eq!(
+            buckets,
+            BTreeMap::from([(0, 2), (100, 1), (900, 1), (1000, 2)])
+        );
+        assert!(bucket_intervals([], 50).is_empty());
+    }
+
+    #[test]
+    fn histogram_lists_the_buckets() {
+        let mut histogram = IntervalHistogram::new(NOTIFICATION_BUCKET_MS);
+        assert_eq!(histogram.to_string(), "  -\n");
+        for millis in [1000, 1010, 2050] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.total, 3);
+        let lines: Vec<_> = histogram.to_string().lines().map(str::to_owned).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("   1000-1099  ms     2 "));
+        assert!(lines[1].starts_with("   2000-2099  ms     1 "));
+    }
+}
@@ -0,0 +1,16 @@
+/// Lower bounds of the heart rate zones as fractions of the maximum heart rate.
+const ZONE_BANDS: [f32; 5] = [0.5, 0.6, 0.7, 0.8, 0.9];
+
+/// Lower bound of every zone in bpm.
+pub fn boundaries(max_heart_rate: u16) -> [u16; 5] {
+    ZONE_BANDS.map(|band| (band * f32::from(max_heart_rate)).round() as u16)
+}
+
+/// The zone from 0 to 4 a heart rate is in, 0 also covering everything below
+/// the first zone.
+pub fn zone(heart_rate: u16, max_heart_rate: u16) -> u8 {
+    boundaries(max_heart_rate)[1..]
+        .iter()
+        .filter(|boundary| heart_rate >= **boundary)
+        .count() as u8
+}
@@ -0,0 +1,177 @@
+//! Runs the binary without Bluetooth, through --simulate or --reprocess, and
+//! checks the OSC it sends to a receiver on the loopback interface.
+
+use rosc::{OscMessage, OscPacket, OscType};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const HEART_RATE_CHARACTERISTIC: &str = "00002a37-0000-1000-8000-00805f9b34fb";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A directory of its own for every test, as they run in parallel.
+fn test_directory(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("vrc_sa_node-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn receiver() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    socket
+}
+
+/// Starts the binary sending to `receiver`, with an empty configuration so
+/// one in the home directory can't change the outcome.
+fn spawn(directory: &Path, receiver: &UdpSocket, arguments: &[&str]) -> Child {
+    let config = directory.join("config.toml");
+    fs::write(&config, "").unwrap();
+    Command::new(env!("CARGO_BIN_EXE_vrc_sa_node"))
+        .current_dir(directory)
+        .arg("--config")
+        .arg(&config)
+        .args(["--no-remember", "--no-log", "--sender", "127.0.0.1:0"])
+        .arg("--receiver")
+        .arg(receiver.local_addr().unwrap().to_string())
+        .args(arguments)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn flatten(packet: OscPacket, messages: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(message) => messages.push(message),
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                flatten(packet, messages);
+            }
+        }
+    }
+}
+
+/// Receives messages until `done` is satisfied with them, or panics after a
+/// while.
+fn receive_until(receiver: &UdpSocket, done: impl Fn(&[OscMessage]) -> bool) -> Vec<OscMessage> {
+    let started = Instant::now();
+    let mut messages = vec![];
+    let mut buffer = [0; rosc::decoder::MTU];
+    while !done(&messages) {
+        assert!(started.elapsed() < TIMEOUT, "Received only {:?}", messages);
+        match receiver.recv(&mut buffer) {
+            Ok(length) => {
+                let (_, packet) = rosc::decoder::decode_udp(&buffer[..length]).unwrap();
+                flatten(packet, &mut messages);
+            }
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(error) => panic!("{}", error),
+        }
+    }
+    messages
+}
+
+fn arguments_of<'a>(messages: &'a [OscMessage], address: &str) -> Vec<&'a [OscType]> {
+    messages
+        .iter()
+        .filter(|message| message.addr == address)
+        .map(|message| &message.args[..])
+        .collect()
+}
+
+#[test]
+fn simulate_sends_heart_rate() {
+    let directory = test_directory("simulate");
+    let receiver = receiver();
+    let mut child = spawn(
+        &directory,
+        &receiver,
+        &["--simulate", "--simulate-bpm", "120"],
+    );
+
+    let messages = receive_until(&receiver, |messages| {
+        !arguments_of(messages, "/avatar/parameters/HeartRateInt").is_empty()
+    });
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRateConnected")[0],
+        [OscType::Bool(true)]
+    );
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRateInt")[0],
+        [OscType::Int(120)]
+    );
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRate")[0],
+        [OscType::Float(0.6)]
+    );
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn reprocess_decodes_measurements() {
+    let directory = test_directory("reprocess");
+    let capture = directory.join("capture.txt");
+    let lines = [
+        // An 8-bit heart rate of 72.
+        "0048",
+        // A 16-bit heart rate of 150 with two RR intervals.
+        "11960000040004",
+        // An 8-bit heart rate of 80 with 65530 kJ expended, before an RR interval.
+        "1850faff0004",
+        // The energy expended counter wrapping around to 4 kJ.
+        "08500400",
+    ]
+    .map(|value| {
+        format!(
+            "2026-01-01T00:00:00.000+00:00 {} {}",
+            HEART_RATE_CHARACTERISTIC, value
+        )
+    });
+    fs::write(&capture, lines.join("\n")).unwrap();
+    let receiver = receiver();
+    let capture = capture.to_string_lossy();
+    let mut child = spawn(
+        &directory,
+        &receiver,
+        &["--reprocess", &capture, "--reprocess-fast"],
+    );
+
+    let messages = receive_until(&receiver, |messages| {
+        arguments_of(messages, "/avatar/parameters/HeartRateInt").len() == 4
+    });
+    assert!(child.wait().unwrap().success());
+
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRateInt"),
+        [
+            [OscType::Int(72)],
+            [OscType::Int(150)],
+            [OscType::Int(80)],
+            [OscType::Int(80)]
+        ]
+    );
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRate"),
+        [
+            [OscType::Float(0.36)],
+            [OscType::Float(0.75)],
+            [OscType::Float(0.4)],
+            [OscType::Float(0.4)]
+        ]
+    );
+    assert_eq!(
+        arguments_of(&messages, "/avatar/parameters/HeartRateEnergy"),
+        [[OscType::Int(0)], [OscType::Int(10)]]
+    );
+    fs::remove_dir_all(directory).unwrap();
+}